@@ -29,8 +29,8 @@ done
 
 echo ''
 echo "let's use some eval!"
-evaluation=$(target/debug/tomato --format bash get testcases.phrases ./fixtures/sample.toml)
-eval "result=$evaluation"
+evaluation=$(target/debug/tomato --format bash --var result get testcases.phrases ./fixtures/sample.toml)
+eval "$evaluation"
 for i in "${result[@]}"; do
 	echo "eval: $i"
 done
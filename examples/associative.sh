@@ -14,6 +14,6 @@ echo ''
 echo "let's evaluate that text!"
 evaluation=$(target/debug/tomato --format bash get testcases.inline_table ./fixtures/sample.toml)
 eval "$evaluation"
-for i in "${!bashval[@]}"; do
-	echo "key: $i	value: ${bashval[$i]}"
+for i in "${!inline_table[@]}"; do
+	echo "key: $i	value: ${inline_table[$i]}"
 done
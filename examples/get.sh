@@ -18,7 +18,7 @@ cat Cargo.toml | target/debug/tomato get package.version
 # should print our current version!
 
 target/debug/tomato --format bash get package.categories Cargo.toml
-# ( command-line-utilities toml )
+# declare -a categories=( 'command-line-utilities' 'toml' )
 
 target/debug/tomato get package.categories Cargo.toml
 # ( "command-line-utilities" "toml" )
@@ -1,57 +1,476 @@
-/// Implement serialization into strings that can be eval-ed in bash.
+/// Implement serialization into strings that can be eval-ed in bash, and the tree
+/// walk shared by every other shell.
+use std::str::FromStr;
 use toml_edit::{Item, Value};
 
-/// Format a toml_edit::Item and all child items as eval-able bash, if possible.
-pub fn format_bash(item: &Item) -> String {
-    // 'ware hackery!
+use crate::{KeySegment, Keyspec};
+
+#[derive(Clone, Copy, Debug)]
+/// The case to use for the names of flattened variables.
+pub enum Case {
+    Upper,
+    Lower,
+    /// Keep the case the keys have in the toml file
+    Preserve,
+}
+
+impl FromStr for Case {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "upper" => Ok(Case::Upper),
+            "lower" => Ok(Case::Lower),
+            "preserve" => Ok(Case::Preserve),
+            _ => Err(anyhow::anyhow!("{input} is not a case tomato knows about")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Options for bash and other shell output.
+pub struct BashOptions {
+    /// The variable to assign to, instead of one named after the key.
+    pub var: Option<String>,
+    /// Flatten tables into one variable per value, even ones that could be written
+    /// as an associative array.
+    pub flatten: bool,
+    /// Start flattened names with this instead of the variable name.
+    pub prefix: Option<String>,
+    /// Join the parts of flattened names with this.
+    pub separator: String,
+    /// The case of flattened names.
+    pub case: Case,
+    /// Make fish variables global and exported instead of local.
+    pub exported: bool,
+}
+
+impl Default for BashOptions {
+    fn default() -> Self {
+        BashOptions {
+            var: None,
+            flatten: false,
+            prefix: None,
+            separator: "_".to_string(),
+            case: Case::Upper,
+            exported: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What sort of value is being assigned to a variable.
+pub enum Kind {
+    Scalar,
+    List,
+    Map,
+}
+
+/// How a shell writes values and assigns them to variables. The tree walk in
+/// `format_shell` is shared by every shell; this is everything that differs.
+pub trait Dialect {
+    /// Quote a string as a literal the shell reads back as exactly that string.
+    fn quote(&self, s: &str) -> String;
+    /// Write a boolean. Most shells have no booleans, so by default this is 1 or 0.
+    fn boolean(&self, b: bool) -> String {
+        if b { "1" } else { "0" }.to_string()
+    }
+    /// Write a float.
+    fn float(&self, f: f64) -> String {
+        f.to_string()
+    }
+    /// Write a datetime. By default it's a string.
+    fn datetime(&self, dt: &toml_edit::Datetime) -> String {
+        self.quote(&dt.to_string())
+    }
+    /// The value assigned for a missing key.
+    fn nothing(&self) -> String {
+        String::new()
+    }
+    /// Write a list of values that have already been written.
+    fn list(&self, items: &[String]) -> String;
+    /// Write a table of values that have already been written, or None if the shell
+    /// has nothing like an associative array.
+    fn map(&self, entries: &[(String, String)]) -> Option<String>;
+    /// True if lists and maps can hold other lists and maps.
+    fn nests(&self) -> bool {
+        false
+    }
+    /// Assign a value that has already been written to a variable.
+    fn assign(&self, name: &str, kind: Kind, value: &str) -> String;
+    /// Assign a table of values that have already been written to a variable, or None
+    /// if the shell has nothing like an associative array.
+    fn assign_map(&self, name: &str, entries: &[(String, String)]) -> Option<String> {
+        Some(self.assign(name, Kind::Map, &self.map(entries)?))
+    }
+}
+
+/// Bash: indexed arrays and associative arrays, neither of which can nest.
+pub struct Bash;
+
+impl Dialect for Bash {
+    fn quote(&self, s: &str) -> String {
+        quote(s)
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        format!("( {} )", items.join(" "))
+    }
+
+    fn map(&self, entries: &[(String, String)]) -> Option<String> {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("[{}]={v}", quote_key(k)))
+            .collect();
+        Some(format!("( {} )", entries.join(" ")))
+    }
+
+    fn assign(&self, name: &str, kind: Kind, value: &str) -> String {
+        match kind {
+            Kind::Scalar => format!("{name}={value}"),
+            Kind::List => format!("declare -a {name}={value}"),
+            Kind::Map => format!("declare -A {name}={value}"),
+        }
+    }
+
+    /// Associative arrays are declared, then filled in one key per line.
+    fn assign_map(&self, name: &str, entries: &[(String, String)]) -> Option<String> {
+        let mut lines = vec![format!("declare -A {name}=()")];
+        lines.extend(
+            entries
+                .iter()
+                .map(|(k, v)| format!("{name}[{}]={v}", quote_key(k))),
+        );
+        Some(lines.join("\n"))
+    }
+}
+
+/// Format a toml_edit::Item as code that assigns it to the named variable in the given
+/// shell. Anything the shell can't write as a single variable is flattened into one
+/// variable per value, as is everything if flattening was asked for.
+pub fn format_shell(shell: &dyn Dialect, item: &Item, name: &str, options: &BashOptions) -> String {
+    if item.is_none() {
+        return shell.assign(name, Kind::Scalar, &shell.nothing());
+    }
+    if options.flatten || (!shell.nests() && needs_flattening(item)) {
+        return format_flattened(shell, item, name, options);
+    }
+    let assignment = match item {
+        Item::Value(Value::InlineTable(_)) | Item::Table(_) => {
+            render_entries(shell, item).and_then(|entries| shell.assign_map(name, &entries))
+        }
+        Item::Value(Value::Array(_)) | Item::ArrayOfTables(_) => {
+            render_item(shell, item).map(|value| shell.assign(name, Kind::List, &value))
+        }
+        _ => render_item(shell, item).map(|value| shell.assign(name, Kind::Scalar, &value)),
+    };
+    // None means the shell has no associative arrays.
+    assignment.unwrap_or_else(|| format_flattened(shell, item, name, options))
+}
+
+/// Write each entry of a table as a literal in the given shell, or None if the item
+/// isn't a table or holds a table the shell has no way to write.
+fn render_entries(shell: &dyn Dialect, item: &Item) -> Option<Vec<(String, String)>> {
     match item {
-        Item::None => "".to_string(),
-        Item::Value(v) => format_bash_value(v.clone()),
-        Item::Table(table) => {
-            let mut lines = vec!["declare -A bashval".to_string()];
-            table.iter().for_each(|(k, v)| {
-                lines.push(format!("bashval[{k}]={}", format_bash(v)));
-            });
-            lines.join("\n")
+        Item::Table(table) => table
+            .iter()
+            .map(|(k, v)| Some((k.to_string(), render_item(shell, v)?)))
+            .collect(),
+        Item::Value(Value::InlineTable(table)) => table
+            .iter()
+            .map(|(k, v)| Some((k.to_string(), render_value(shell, v)?)))
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Write an item as a literal in the given shell, or None if it holds a table and the
+/// shell has no way to write one.
+fn render_item(shell: &dyn Dialect, item: &Item) -> Option<String> {
+    match item {
+        Item::None => Some(shell.nothing()),
+        Item::Value(v) => render_value(shell, v),
+        Item::Table(_) => shell.map(&render_entries(shell, item)?),
+        Item::ArrayOfTables(aot) => {
+            let items = aot
+                .iter()
+                .map(|table| render_item(shell, &Item::Table(table.clone())))
+                .collect::<Option<Vec<String>>>()?;
+            Some(shell.list(&items))
         }
-        // TODO: This bails and emits toml. It might instead emit a lot of
-        // more usable bash, but... tbh in this situation the caller should
-        // snag json and pass it to jq.
-        Item::ArrayOfTables(aot) => aot.to_string(),
-    }
-}
-
-/// Format a toml_edit::Value as a bash data type, if possible
-fn format_bash_value(v: Value) -> String {
-    match v {
-        Value::String(s) => s.to_string().trim().to_string(),
-        Value::Integer(i) => i.into_value().to_string(),
-        Value::Float(f) => f.into_value().to_string(),
-        Value::Boolean(b) => match b.into_value() {
-            true => "1".to_string(),
-            false => "0".to_string(),
-        },
-        Value::Datetime(dt) => dt.into_value().to_string(),
+    }
+}
+
+/// Write a value as a literal in the given shell, or None if it holds a table and the
+/// shell has no way to write one.
+fn render_value(shell: &dyn Dialect, v: &Value) -> Option<String> {
+    let rendered = match v {
+        Value::String(s) => shell.quote(s.value()),
+        Value::Integer(i) => i.value().to_string(),
+        Value::Float(f) => shell.float(*f.value()),
+        Value::Boolean(b) => shell.boolean(*b.value()),
+        Value::Datetime(dt) => shell.datetime(dt.value()),
         Value::Array(array) => {
-            let output = array
+            let items = array
                 .iter()
-                .map(|xs| format_bash_value(xs.clone()).trim().to_owned())
-                .collect::<Vec<String>>()
-                .join(" ");
-            format!("( {output} )")
+                .map(|v| render_value(shell, v))
+                .collect::<Option<Vec<String>>>()?;
+            shell.list(&items)
         }
         Value::InlineTable(table) => {
-            // this could be better. probably should add a keyname param all the way up
-            // the chain to make this case work
-            let mut lines = vec!["declare -A bashval".to_string()];
-            table.iter().for_each(|(k, v)| {
-                lines.push(format!("bashval[{k}]={}", format_bash_value(v.clone())));
-            });
-            lines.join("\n")
+            let entries = table
+                .iter()
+                .map(|(k, v)| Some((k.to_string(), render_value(shell, v)?)))
+                .collect::<Option<Vec<(String, String)>>>()?;
+            shell.map(&entries)?
         }
+    };
+    Some(rendered)
+}
+
+/// True for values that fit in a single bash variable or array element.
+fn is_scalar(v: &Value) -> bool {
+    !matches!(v, Value::Array(_) | Value::InlineTable(_))
+}
+
+/// True if the item has something nested inside it that bash has no way to write.
+fn needs_flattening(item: &Item) -> bool {
+    match item {
+        Item::None => false,
+        Item::Value(Value::Array(array)) => !array.iter().all(is_scalar),
+        Item::Value(Value::InlineTable(table)) => !table.iter().all(|(_, v)| is_scalar(v)),
+        Item::Value(_) => false,
+        Item::Table(table) => !table
+            .iter()
+            .all(|(_, v)| v.as_value().map(is_scalar).unwrap_or(false)),
+        Item::ArrayOfTables(_) => true,
     }
 }
 
+/// Write every value in the item as its own variable, named for its path through the
+/// item: `SERVER_HTTP_PORT=8080`. Arrays of scalars stay arrays; arrays holding
+/// anything else are flattened with their indexes as part of the name.
+fn format_flattened(shell: &dyn Dialect, item: &Item, name: &str, options: &BashOptions) -> String {
+    flatten(item, name, options, false)
+        .into_iter()
+        .filter_map(|(name, v)| {
+            let kind = match v {
+                Value::Array(_) => Kind::List,
+                _ => Kind::Scalar,
+            };
+            Some(shell.assign(&name, kind, &render_value(shell, &v)?))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Flatten the item into a list of variable names and the values they should hold.
+/// Names are made from the prefix (or the given name, if there's no prefix) and the
+/// path to each value. Arrays of scalars are kept whole unless `split_arrays` is set,
+/// in which case every element gets a variable of its own. A missing item is an
+/// empty string. Values that would end up with an empty name are left out.
+pub fn flatten(
+    item: &Item,
+    name: &str,
+    options: &BashOptions,
+    split_arrays: bool,
+) -> Vec<(String, Value)> {
+    let prefix = options.prefix.as_deref().unwrap_or(name);
+    let mut path: Vec<String> = Vec::new();
+    if !prefix.is_empty() {
+        path.push(prefix.to_string());
+    }
+    let mut found = Vec::new();
+    if item.is_none() {
+        found.extend(flat_name(&path, options).map(|name| (name, Value::from(""))));
+    }
+    let mut flattener = Flattener {
+        path,
+        found,
+        options,
+        split_arrays,
+    };
+    flattener.item(item);
+    flattener.found
+}
+
+/// The state of a walk through a tree of items, collecting flattened values.
+struct Flattener<'a> {
+    path: Vec<String>,
+    found: Vec<(String, Value)>,
+    options: &'a BashOptions,
+    split_arrays: bool,
+}
+
+impl Flattener<'_> {
+    fn item(&mut self, item: &Item) {
+        match item {
+            Item::None => {}
+            Item::Value(v) => self.value(v),
+            Item::Table(table) => table.iter().for_each(|(k, v)| {
+                self.path.push(k.to_string());
+                self.item(v);
+                self.path.pop();
+            }),
+            Item::ArrayOfTables(aot) => aot.iter().enumerate().for_each(|(idx, table)| {
+                self.path.push(idx.to_string());
+                table.iter().for_each(|(k, v)| {
+                    self.path.push(k.to_string());
+                    self.item(v);
+                    self.path.pop();
+                });
+                self.path.pop();
+            }),
+        }
+    }
+
+    fn value(&mut self, v: &Value) {
+        match v {
+            Value::InlineTable(table) => table.iter().for_each(|(k, v)| {
+                self.path.push(k.to_string());
+                self.value(v);
+                self.path.pop();
+            }),
+            Value::Array(array) if self.split_arrays || !array.iter().all(is_scalar) => {
+                array.iter().enumerate().for_each(|(idx, v)| {
+                    self.path.push(idx.to_string());
+                    self.value(v);
+                    self.path.pop();
+                })
+            }
+            _ => self
+                .found
+                .extend(flat_name(&self.path, self.options).map(|name| (name, v.clone()))),
+        }
+    }
+}
+
+/// Join the path to a flattened value into a variable name, or None if there's
+/// nothing to name it after.
+fn flat_name(path: &[String], options: &BashOptions) -> Option<String> {
+    let joined = path
+        .iter()
+        .map(|segment| sanitize_chars(segment))
+        .collect::<Vec<String>>()
+        .join(&options.separator);
+    let cased = match options.case {
+        Case::Upper => joined.to_uppercase(),
+        Case::Lower => joined.to_lowercase(),
+        Case::Preserve => joined,
+    };
+    if cased.is_empty() {
+        return None;
+    }
+    Some(sanitize(&cased))
+}
+
+/// Format the results of several lookups as one variable assignment per key.
+/// Variables are named after the full key, so `package.name` becomes `package_name`.
+pub fn format_shell_assignments(
+    shell: &dyn Dialect,
+    found: &[(Keyspec, Item)],
+    options: &BashOptions,
+) -> String {
+    found
+        .iter()
+        .map(|(key, item)| format_shell(shell, item, &variable_name(key), options))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Turn a key into something usable as a bash variable name.
+pub fn variable_name(key: &Keyspec) -> String {
+    let joined = key
+        .subkeys
+        .iter()
+        .map(|k| k.to_string())
+        .collect::<Vec<String>>()
+        .join("_");
+    sanitize(&joined)
+}
+
+/// The variable name to use for a single value: the last name in its key, so
+/// `package.metadata.docs` becomes `docs`.
+pub fn default_name(key: &Keyspec) -> String {
+    match key.subkeys.iter().rev().find_map(|k| match k {
+        KeySegment::Name(n) => Some(n),
+        KeySegment::Index(_) => None,
+    }) {
+        Some(n) => sanitize(n),
+        None => "bashval".to_string(),
+    }
+}
+
+/// Replace anything bash doesn't allow in a variable name.
+fn sanitize(name: &str) -> String {
+    let mut name = sanitize_chars(name);
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Replace every character that can't appear in a variable name with an underscore.
+fn sanitize_chars(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Check a variable name given on the command line.
+pub fn parse_var_name(name: &str) -> Result<String, anyhow::Error> {
+    if name.is_empty() || sanitize(name) != name {
+        anyhow::bail!("{name} is not a valid bash variable name");
+    }
+    Ok(name.to_string())
+}
+
+/// Check a prefix or separator for flattened names given on the command line. These
+/// may be empty, but may only hold characters that can go in a variable name.
+pub fn parse_name_part(part: &str) -> Result<String, anyhow::Error> {
+    if sanitize_chars(part) != part {
+        anyhow::bail!("{part} can't be used in a bash variable name");
+    }
+    Ok(part.to_string())
+}
+
+/// Quote an associative array key, unless it's made of characters that are
+/// safe as they are.
+fn quote_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+/// Quote a string as a bash literal that evaluates to exactly that string. Nothing
+/// inside single quotes is special to bash, so that's what we use unless the string
+/// holds control characters, which need `$'...'` quoting to survive.
+pub fn quote(s: &str) -> String {
+    if !s.chars().any(|c| c.is_control()) {
+        return format!("'{}'", s.replace('\'', r"'\''"));
+    }
+    let mut quoted = String::from("$'");
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\"),
+            '\'' => quoted.push_str(r"\'"),
+            '\n' => quoted.push_str(r"\n"),
+            '\t' => quoted.push_str(r"\t"),
+            '\r' => quoted.push_str(r"\r"),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -59,6 +478,10 @@ mod tests {
     use std::str::FromStr;
     use toml_edit::Document;
 
+    fn format_bash(item: &Item, name: &str, options: &BashOptions) -> String {
+        format_shell(&Bash, item, name, options)
+    }
+
     #[test]
     fn bash_ouput() {
         let toml = include_str!("../fixtures/sample.toml");
@@ -68,28 +491,39 @@ mod tests {
 
         let key = Keyspec::from_str("testcases.hashes.mats").unwrap();
         let item = get_key(&mut doc, &key).expect("expected to find key testcases.hashes.mats");
-        let formatted = format_bash(&item);
-        assert_eq!(formatted, r#"( "potatoes" "salt" "oil" "frying" )"#);
+        let formatted = format_bash(&item, &default_name(&key), &BashOptions::default());
+        assert_eq!(
+            formatted,
+            r#"declare -a mats=( 'potatoes' 'salt' 'oil' 'frying' )"#
+        );
 
         let key = Keyspec::from_str("testcases.numbers").unwrap();
         let item = get_key(&mut doc, &key).expect("expected to find key testcases.numbers");
-        let formatted = format_bash(&item);
-        assert_eq!(formatted, r#"( 1 3 5 7 11 13 17 23 )"#);
+        let formatted = format_bash(&item, "primes", &BashOptions::default());
+        assert_eq!(formatted, r#"declare -a primes=( 1 3 5 7 11 13 17 23 )"#);
 
         let key = Keyspec::from_str("testcases.hashes.color").unwrap();
         let item = get_key(&mut doc, &key).expect("expected to find key testcases.numbers");
-        let formatted = format_bash(&item);
-        assert_eq!(formatted, r#""brown""#);
+        let formatted = format_bash(&item, &default_name(&key), &BashOptions::default());
+        assert_eq!(formatted, r#"color='brown'"#);
 
         let key = Keyspec::from_str("testcases.are_passing").unwrap();
         let item = get_key(&mut doc, &key).expect("expected to find key testcases.are_passing");
-        let formatted = format_bash(&item);
-        assert_eq!(formatted, r#"1"#);
+        let formatted = format_bash(&item, "passing", &BashOptions::default());
+        assert_eq!(formatted, r#"passing=1"#);
 
         let key = Keyspec::from_str("testcases.are_complete").unwrap();
         let item = get_key(&mut doc, &key).expect("expected to find key testcases.are_complete");
-        let formatted = format_bash(&item);
-        assert_eq!(formatted, r#"0"#);
+        let formatted = format_bash(&item, "complete", &BashOptions::default());
+        assert_eq!(formatted, r#"complete=0"#);
+
+        let key = Keyspec::from_str("nested[1].entry").unwrap();
+        assert_eq!(default_name(&key), "entry");
+        let key = Keyspec::from_str("testcases.fruits[1]").unwrap();
+        assert_eq!(default_name(&key), "fruits");
+        assert!(parse_var_name("my_var2").is_ok());
+        assert!(parse_var_name("2var").is_err());
+        assert!(parse_var_name("a;b").is_err());
     }
 
     #[test]
@@ -97,11 +531,11 @@ mod tests {
         let toml = r#"
 name = "testtable"
 inline_table = { catname = "Kitsune", fruit = "kumquat", "safe_pet" = true, class = "Archaeologist" }"#;
-        let expected = r#"declare -A bashval
-bashval[catname]="Kitsune"
-bashval[fruit]="kumquat"
-bashval[safe_pet]=1
-bashval[class]="Archaeologist""#;
+        let expected = r#"declare -A inline_table=()
+inline_table[catname]='Kitsune'
+inline_table[fruit]='kumquat'
+inline_table[safe_pet]=1
+inline_table[class]='Archaeologist'"#;
 
         let mut doc = toml
             .parse::<Document>()
@@ -109,7 +543,159 @@ bashval[class]="Archaeologist""#;
 
         let key = Keyspec::from_str("inline_table").unwrap();
         let item = get_key(&mut doc, &key).expect("expected to get key 'inline_table'");
-        let bashified = format_bash(&item);
+        let bashified = format_bash(&item, &default_name(&key), &BashOptions::default());
         assert_eq!(bashified, expected);
     }
+
+    #[test]
+    fn bash_assignments() {
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+
+        let keys = crate::KeyList::from_str("testcases.hashes.color,testcases.numbers,when-not=3")
+            .unwrap();
+        let found = crate::get_keys(&mut doc, &keys, None).expect("lookups should succeed");
+        assert_eq!(
+            format_shell_assignments(&Bash, &found, &BashOptions::default()),
+            r#"testcases_hashes_color='brown'
+declare -a testcases_numbers=( 1 3 5 7 11 13 17 23 )
+when_not=3"#
+        );
+
+        let key = Keyspec::from_str("9lives.mats[2]").unwrap();
+        assert_eq!(variable_name(&key), "_9lives_mats_2");
+    }
+
+    #[test]
+    fn bash_hostile_strings() {
+        let toml = r#"
+dollars = "$HOME and `whoami` and $(rm -rf /)"
+quotes = "it's \"quoted\""
+escapes = "line\nbreak\ttab\\slash\u0007bell"
+list = ["$(reboot)", "a'b", "x\ny", [1, 2]]
+"weird key" = { "$(id)" = "`id`", "ok" = { nested = true } }
+"plain key" = { "$(id)" = "`id`", "ok" = "fine" }
+
+[[tables]]
+name = "$(id)"
+"#;
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let mut bash = |key: &str| {
+            let key = Keyspec::from_str(key).unwrap();
+            format_bash(
+                &get_key(&mut doc, &key).unwrap(),
+                "v",
+                &BashOptions::default(),
+            )
+        };
+
+        assert_eq!(bash("dollars"), "v='$HOME and `whoami` and $(rm -rf /)'");
+        assert_eq!(bash("quotes"), r#"v='it'\''s "quoted"'"#);
+        assert_eq!(bash("escapes"), r"v=$'line\nbreak\ttab\\slash\x07bell'");
+        assert_eq!(
+            bash("list"),
+            r#"V_0='$(reboot)'
+V_1='a'\''b'
+V_2=$'x\ny'
+declare -a V_3=( 1 2 )"#
+        );
+        assert_eq!(bash("tables"), "V_0_NAME='$(id)'");
+        let key = Keyspec::from_str("weird key").unwrap();
+        assert_eq!(
+            format_bash(
+                &get_key(&mut doc, &key).unwrap(),
+                "weird",
+                &BashOptions::default()
+            ),
+            "WEIRD___ID_='`id`'\nWEIRD_OK_NESTED=1"
+        );
+        let key = Keyspec::from_str("plain key").unwrap();
+        assert_eq!(
+            format_bash(
+                &get_key(&mut doc, &key).unwrap(),
+                "plain",
+                &BashOptions::default()
+            ),
+            "declare -A plain=()\nplain['$(id)']='`id`'\nplain[ok]='fine'"
+        );
+    }
+
+    #[test]
+    fn bash_flattened() {
+        let toml = r#"
+[server]
+host = "localhost"
+ports = [80, 443]
+tls = { cert = "a.pem", "key-file" = "a.key" }
+
+[server.http]
+timeout = 30
+
+[[server.backend]]
+name = "one"
+
+[[server.backend]]
+name = "two"
+weights = [[1, 2], [3]]
+"#;
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let key = Keyspec::from_str("server").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+
+        assert_eq!(
+            format_bash(&item, "server", &BashOptions::default()),
+            r#"SERVER_HOST='localhost'
+declare -a SERVER_PORTS=( 80 443 )
+SERVER_TLS_CERT='a.pem'
+SERVER_TLS_KEY_FILE='a.key'
+SERVER_HTTP_TIMEOUT=30
+SERVER_BACKEND_0_NAME='one'
+SERVER_BACKEND_1_NAME='two'
+declare -a SERVER_BACKEND_1_WEIGHTS_0=( 1 2 )
+declare -a SERVER_BACKEND_1_WEIGHTS_1=( 3 )"#
+        );
+
+        let options = BashOptions {
+            prefix: Some("".to_string()),
+            separator: "__".to_string(),
+            case: Case::Preserve,
+            ..Default::default()
+        };
+        let key = Keyspec::from_str("server.tls").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+        assert_eq!(
+            format_bash(&item, "tls", &options),
+            "declare -A tls=()\ntls[cert]='a.pem'\ntls[key-file]='a.key'"
+        );
+        let key = Keyspec::from_str("server.backend").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+        assert_eq!(
+            format_bash(&item, "backend", &options),
+            r#"_0__name='one'
+_1__name='two'
+declare -a _1__weights__0=( 1 2 )
+declare -a _1__weights__1=( 3 )"#
+        );
+
+        let key = Keyspec::from_str("server.http").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+
+        // Flat tables are associative arrays unless flattening is asked for.
+        let options = BashOptions {
+            flatten: true,
+            case: Case::Lower,
+            ..Default::default()
+        };
+        assert_eq!(format_bash(&item, "http", &options), "http_timeout=30");
+        assert_eq!(
+            format_bash(&item, "http", &BashOptions::default()),
+            "declare -A http=()\nhttp[timeout]=30"
+        );
+    }
 }
@@ -0,0 +1,236 @@
+/// Parse lists of edit operations and apply them all to a single document.
+use std::fmt::Display;
+use std::str::FromStr;
+
+use toml_edit::{Document, Item, Value};
+
+use crate::json::json_to_value;
+use crate::{append_value, remove_key, set_key, Change, Keyspec, TomlVal};
+
+#[derive(Clone, Debug)]
+/// One edit to apply to a document.
+pub enum Operation {
+    Set { key: Keyspec, value: Value },
+    Rm { key: Keyspec },
+    Append { key: Keyspec, value: Value },
+}
+
+impl Operation {
+    /// The name of this operation, as it is spelled in an ops list.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Set { .. } => "set",
+            Operation::Rm { .. } => "rm",
+            Operation::Append { .. } => "append",
+        }
+    }
+
+    /// The key this operation touches.
+    pub fn key(&self) -> &Keyspec {
+        match self {
+            Operation::Set { key, .. } => key,
+            Operation::Rm { key } => key,
+            Operation::Append { key, .. } => key,
+        }
+    }
+
+    /// Apply this operation to the document, responding with the previous value
+    /// at its key.
+    pub fn apply(&self, toml: &mut Document) -> Result<Item, anyhow::Error> {
+        match self {
+            Operation::Set { key, value } => set_key(toml, key, value),
+            Operation::Rm { key } => remove_key(toml, key),
+            Operation::Append { key, value } => append_value(toml, key, value.clone()),
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name(), self.key())
+    }
+}
+
+/// Parse a list of operations. Input that looks like a json array is read as
+/// a list of `{"op": "set", "key": "a.b", "value": 1}` objects. Anything else is
+/// read one operation per line: `set key value`, `rm key`, or `append key value`.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn parse_ops(input: &str) -> Result<Vec<Operation>, anyhow::Error> {
+    if input.trim_start().starts_with('[') {
+        parse_json_ops(input)
+    } else {
+        parse_line_ops(input)
+    }
+}
+
+/// Parse the one-operation-per-line form. Values for `set` are interpreted exactly
+/// as they would be on the command line, so `"true"` is a string and `true` is a
+/// boolean. Values for `append` are always strings, also as on the command line.
+fn parse_line_ops(input: &str) -> Result<Vec<Operation>, anyhow::Error> {
+    let mut ops = Vec::new();
+    for (idx, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let lineno = idx + 1;
+        let (op, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim_start();
+        let (key, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let value = value.trim();
+        if key.is_empty() {
+            anyhow::bail!("line {lineno}: operation '{op}' needs a key");
+        }
+        let key = Keyspec::from_str(key)?;
+
+        let operation = match op {
+            "set" | "append" if value.is_empty() => {
+                anyhow::bail!("line {lineno}: operation '{op}' needs a value");
+            }
+            "set" => Operation::Set {
+                key,
+                value: TomlVal::from_str(value)?.inner,
+            },
+            "append" => Operation::Append {
+                key,
+                value: value.into(),
+            },
+            "rm" => {
+                if !value.is_empty() {
+                    anyhow::bail!("line {lineno}: operation 'rm' takes only a key");
+                }
+                Operation::Rm { key }
+            }
+            _ => anyhow::bail!("line {lineno}: '{op}' is not a known operation"),
+        };
+        ops.push(operation);
+    }
+    Ok(ops)
+}
+
+/// Parse the json array form. Values are converted using their json types.
+fn parse_json_ops(input: &str) -> Result<Vec<Operation>, anyhow::Error> {
+    let parsed: Vec<serde_json::Value> = serde_json::from_str(input)?;
+    parsed
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| {
+            let field = |name: &str| {
+                entry.get(name).ok_or_else(|| {
+                    anyhow::anyhow!("operation {} is missing the field '{name}'", idx + 1)
+                })
+            };
+            let op = field("op")?
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("operation {}: 'op' must be a string", idx + 1))?;
+            let key = field("key")?
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("operation {}: 'key' must be a string", idx + 1))?;
+            let key = Keyspec::from_str(key)?;
+            match op {
+                "set" => Ok(Operation::Set {
+                    key,
+                    value: json_to_value(field("value")?)?,
+                }),
+                "append" => Ok(Operation::Append {
+                    key,
+                    value: json_to_value(field("value")?)?,
+                }),
+                "rm" => Ok(Operation::Rm { key }),
+                _ => anyhow::bail!("operation {}: '{op}' is not a known operation", idx + 1),
+            }
+        })
+        .collect()
+}
+
+/// Apply every operation to the document in order, responding with a record of
+/// the change each one made. Stops at the first failure; the caller is expected to
+/// discard the document in that case so that a batch is all-or-nothing.
+pub fn apply_ops(toml: &mut Document, ops: &[Operation]) -> Result<Vec<Change>, anyhow::Error> {
+    ops.iter()
+        .enumerate()
+        .map(|(idx, op)| {
+            let original = op
+                .apply(toml)
+                .map_err(|e| anyhow::anyhow!("operation {} ({op}) failed: {e}", idx + 1))?;
+            Change::new(op.name(), op.key(), original, toml)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format_item, get_key, Format};
+
+    #[test]
+    fn line_ops() {
+        let input = r#"
+# bump it
+set testcases.hashes.color "taupe"
+set testcases.are_complete true
+append testcases.fruits   blood orange
+rm testcases.hashes.favorite
+"#;
+        let ops = parse_ops(input).expect("these ops should parse");
+        assert_eq!(ops.len(), 4);
+        assert_eq!(ops[2].to_string(), "append testcases.fruits");
+
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let results = apply_ops(&mut doc, &ops).expect("every op should apply");
+        assert_eq!(format_item(&results[0].old, Format::Raw), "brown");
+        assert_eq!(format_item(&results[0].new, Format::Raw), "taupe");
+        assert_eq!(format_item(&results[1].old, Format::Raw), "0");
+        assert_eq!(format_item(&results[3].old, Format::Raw), "Hobees DeAnza");
+        assert!(results[3].new.is_none());
+
+        let key = Keyspec::from_str("testcases.are_complete").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+        assert_eq!(format_item(&item, Format::Toml), "true");
+        assert!(doc.to_string().contains(r#""persimmon" , "blood orange"]"#));
+        assert!(!doc.to_string().contains("favorite"));
+    }
+
+    #[test]
+    fn json_ops() {
+        let input = r#"[
+            {"op": "set", "key": "testcases.numbers[0]", "value": 2},
+            {"op": "append", "key": "testcases.numbers", "value": 29},
+            {"op": "rm", "key": "testcases.when"}
+        ]"#;
+        let ops = parse_ops(input).expect("these ops should parse");
+        assert_eq!(ops.len(), 3);
+
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        apply_ops(&mut doc, &ops).expect("every op should apply");
+        let key = Keyspec::from_str("testcases.numbers").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+        assert_eq!(format_item(&item, Format::Json), "[2,3,5,7,11,13,17,23,29]");
+    }
+
+    #[test]
+    fn bad_ops() {
+        assert!(parse_ops("frobnicate a.b c").is_err());
+        assert!(parse_ops("set a.b").is_err());
+        assert!(parse_ops("rm a.b c").is_err());
+        assert!(parse_ops(r#"[{"op": "set", "key": "a"}]"#).is_err());
+    }
+
+    #[test]
+    fn failures_stop_the_batch() {
+        let ops = parse_ops("set testcases.fruits[1] fig\nappend testcases.hashes.color red\n")
+            .expect("these ops should parse");
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let err = apply_ops(&mut doc, &ops).expect_err("appending to a string should fail");
+        assert!(err.to_string().contains("operation 2 (append"));
+    }
+}
@@ -0,0 +1,184 @@
+/// Bump semantic version strings, the way a release process would.
+use semver::{Prerelease, Version};
+use std::str::FromStr;
+use toml_edit::{Document, Item, Value};
+
+use crate::{get_key, set_key, Keyspec};
+
+/// The prerelease label used when none is given.
+const DEFAULT_LABEL: &str = "alpha";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Which part of a version to bump.
+pub enum Level {
+    Major,
+    Minor,
+    Patch,
+    Prerelease,
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "major" => Ok(Level::Major),
+            "minor" => Ok(Level::Minor),
+            "patch" => Ok(Level::Patch),
+            "prerelease" | "pre" => Ok(Level::Prerelease),
+            _ => Err(anyhow::anyhow!(
+                "{input} is not a version part; use major, minor, patch, or prerelease"
+            )),
+        }
+    }
+}
+
+/// Bump the version. Bumping a prerelease to the release it leads up to just drops the
+/// prerelease, so `2.0.0-rc.1` bumped to the next major is `2.0.0`. With a prerelease
+/// label, the bumped version is the first prerelease of the next one: `1.2.3` bumped to
+/// the next minor with `beta` is `1.3.0-beta.1`. Bumping the prerelease counts up within
+/// the same label, or starts a new one for a different label. Build metadata is dropped.
+pub fn bump_version(
+    version: &Version,
+    level: Level,
+    label: Option<&str>,
+) -> Result<Version, anyhow::Error> {
+    let mut next = Version::new(version.major, version.minor, version.patch);
+    let released = version.pre.is_empty() || label.is_some();
+    match level {
+        Level::Major if released || version.minor != 0 || version.patch != 0 => {
+            next = Version::new(version.major + 1, 0, 0);
+        }
+        Level::Minor if released || version.patch != 0 => {
+            next = Version::new(version.major, version.minor + 1, 0);
+        }
+        Level::Patch if released => {
+            next = Version::new(version.major, version.minor, version.patch + 1);
+        }
+        Level::Prerelease => {
+            let (current, count) = split_prerelease(&version.pre);
+            let label = label.or(current).unwrap_or(DEFAULT_LABEL);
+            let count = match (current, count) {
+                (Some(current), Some(count)) if current == label => count + 1,
+                _ => 1,
+            };
+            if version.pre.is_empty() {
+                next.patch += 1;
+            }
+            next.pre = Prerelease::new(&format!("{label}.{count}"))?;
+            return Ok(next);
+        }
+        _ => {}
+    }
+    if let Some(label) = label {
+        next.pre = Prerelease::new(&format!("{label}.1"))?;
+    }
+    Ok(next)
+}
+
+/// Split a prerelease like `beta.2` into its label and count, either of which may be
+/// missing.
+fn split_prerelease(pre: &Prerelease) -> (Option<&str>, Option<u64>) {
+    if pre.is_empty() {
+        return (None, None);
+    }
+    match pre.as_str().rsplit_once('.') {
+        Some((label, count)) => match count.parse::<u64>() {
+            Ok(count) => (Some(label), Some(count)),
+            Err(_) => (Some(pre.as_str()), None),
+        },
+        None => match pre.as_str().parse::<u64>() {
+            Ok(count) => (None, Some(count)),
+            Err(_) => (Some(pre.as_str()), None),
+        },
+    }
+}
+
+/// Bump the version string at the key, responding with the original value. A leading
+/// `v` and single quotes are kept.
+pub fn bump_key(
+    toml: &mut Document,
+    key: &Keyspec,
+    level: Level,
+    label: Option<&str>,
+) -> Result<Item, anyhow::Error> {
+    let current = match get_key(toml, key)? {
+        Item::None => anyhow::bail!("key {key} not found"),
+        Item::Value(Value::String(s)) => s,
+        _ => anyhow::bail!("key {key} does not hold a version string"),
+    };
+    let text = current.value();
+    let (prefix, bare) = match text.strip_prefix('v') {
+        Some(bare) => ("v", bare),
+        None => ("", text.as_str()),
+    };
+    let version = Version::parse(bare).map_err(|e| {
+        anyhow::anyhow!("key {key} holds {text}, which is not a semantic version: {e}")
+    })?;
+    let next = format!("{prefix}{}", bump_version(&version, level, label)?);
+
+    let literal = current
+        .as_repr()
+        .and_then(|r| r.as_raw().as_str())
+        .is_some_and(|raw| raw.starts_with('\''));
+    let value = if literal {
+        format!("'{next}'").parse::<Value>()?
+    } else {
+        Value::from(next)
+    };
+    set_key(toml, key, &value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bump(version: &str, level: &str, label: Option<&str>) -> String {
+        let version = Version::parse(version).expect("test versions should be valid");
+        let level = Level::from_str(level).expect("test levels should be valid");
+        bump_version(&version, level, label)
+            .expect("test versions should bump")
+            .to_string()
+    }
+
+    #[test]
+    fn versions_bump() {
+        assert_eq!(bump("1.2.3", "major", None), "2.0.0");
+        assert_eq!(bump("1.2.3", "minor", None), "1.3.0");
+        assert_eq!(bump("1.2.3+build.5", "patch", None), "1.2.4");
+        assert_eq!(bump("2.0.0-rc.1", "major", None), "2.0.0");
+        assert_eq!(bump("1.3.0-rc.1", "major", None), "2.0.0");
+        assert_eq!(bump("1.3.0-rc.1", "minor", None), "1.3.0");
+        assert_eq!(bump("1.2.4-rc.1", "patch", None), "1.2.4");
+        assert_eq!(bump("1.2.3", "minor", Some("beta")), "1.3.0-beta.1");
+        assert_eq!(bump("1.2.3", "prerelease", None), "1.2.4-alpha.1");
+        assert_eq!(bump("1.2.4-alpha.1", "prerelease", None), "1.2.4-alpha.2");
+        assert_eq!(bump("1.2.4-alpha", "prerelease", None), "1.2.4-alpha.1");
+        assert_eq!(
+            bump("1.2.4-alpha.7", "prerelease", Some("rc")),
+            "1.2.4-rc.1"
+        );
+        assert!(Level::from_str("huge").is_err());
+    }
+
+    #[test]
+    fn keys_bump() {
+        let mut doc = "[package]\nversion = 'v0.9.1' # released\nname = \"tomato\"\n"
+            .parse::<Document>()
+            .unwrap();
+        let key = Keyspec::from_str("package.version").unwrap();
+        bump_key(&mut doc, &key, Level::Minor, None).expect("the version should bump");
+        assert_eq!(
+            doc.to_string(),
+            "[package]\nversion = 'v0.10.0' # released\nname = \"tomato\"\n"
+        );
+
+        let key = Keyspec::from_str("package.name").unwrap();
+        let err = bump_key(&mut doc, &key, Level::Patch, None).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("key package.name holds tomato, which is not a semantic version"));
+        let key = Keyspec::from_str("package.edition").unwrap();
+        assert!(bump_key(&mut doc, &key, Level::Patch, None).is_err());
+    }
+}
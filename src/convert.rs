@@ -0,0 +1,153 @@
+/// Convert whole documents among toml, json, and yaml. Everything passes through a
+/// toml_edit::Document on the way, so the toml written is laid out the way a person
+/// would write it.
+use std::str::FromStr;
+use toml_edit::{Document, Item, Table};
+
+use crate::json::{format_json_with, json_to_document, JsonOptions};
+use crate::yaml::format_yaml;
+
+/// Tables with at most this many values, and nothing nested in them, are written inline
+/// unless they're at the top level of the document.
+const INLINE_MAX_KEYS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A document format tomato can read and write.
+pub enum Syntax {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl FromStr for Syntax {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "toml" => Ok(Syntax::Toml),
+            "json" => Ok(Syntax::Json),
+            "yaml" | "yml" => Ok(Syntax::Yaml),
+            _ => Err(anyhow::anyhow!(
+                "{input} is not a format tomato can convert"
+            )),
+        }
+    }
+}
+
+impl Syntax {
+    /// Guess the format of a file from its extension.
+    pub fn from_path(path: &str) -> Option<Syntax> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        Syntax::from_str(extension).ok()
+    }
+}
+
+/// Parse a document written in the given format. Json and yaml documents must be
+/// objects at the top level, and can't hold nulls, since toml has neither.
+pub fn parse_document(text: &str, syntax: Syntax) -> Result<Document, anyhow::Error> {
+    let json: serde_json::Value = match syntax {
+        Syntax::Toml => {
+            return text
+                .parse::<Document>()
+                .map_err(|e| anyhow::anyhow!("The input is not valid toml.\n{e}"))
+        }
+        Syntax::Json => serde_json::from_str(text)?,
+        Syntax::Yaml => serde_yaml::from_str(text)?,
+    };
+    let mut doc = json_to_document(&json)?;
+    tidy(doc.as_table_mut(), 0);
+    Ok(doc)
+}
+
+/// Write a document in the given format.
+pub fn render_document(doc: &Document, syntax: Syntax, options: &JsonOptions) -> String {
+    match syntax {
+        Syntax::Toml => doc.to_string(),
+        Syntax::Json => format!("{}\n", format_json_with(doc.as_item(), options)),
+        Syntax::Yaml => format!("{}\n", format_yaml(doc.as_item())),
+    }
+}
+
+/// Write small tables below the top level inline, the way `serde = { version = "1.0" }`
+/// is usually written, and give headers back to tables that now hold values.
+fn tidy(table: &mut Table, depth: usize) {
+    for (_, item) in table.iter_mut() {
+        match item {
+            Item::Table(child) if depth > 0 && is_small_leaf(child) => {
+                let inline = std::mem::take(item).into_value();
+                if let Ok(value) = inline {
+                    *item = Item::Value(value);
+                }
+            }
+            Item::Table(child) => tidy(child, depth + 1),
+            Item::ArrayOfTables(aot) => aot.iter_mut().for_each(|child| tidy(child, depth + 1)),
+            _ => {}
+        }
+    }
+    if table.iter().any(|(_, item)| item.is_value()) {
+        table.set_implicit(false);
+    }
+}
+
+/// True if the table is short and holds nothing but values.
+fn is_small_leaf(table: &Table) -> bool {
+    table.len() <= INLINE_MAX_KEYS && table.iter().all(|(_, item)| item.is_value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converted_toml_is_tidy() {
+        let yaml = r#"
+package:
+  name: tomato
+  keywords: [cli, toml]
+dependencies:
+  anyhow: "1.0"
+  serde:
+    version: "1.0"
+    features: [derive]
+servers:
+  - name: alpha
+    tls:
+      cert: a.pem
+  - name: beta
+"#;
+        let doc = parse_document(yaml, Syntax::Yaml).expect("test yaml should convert");
+        assert_eq!(
+            render_document(&doc, Syntax::Toml, &JsonOptions::default()),
+            r#"[package]
+name = "tomato"
+keywords = ["cli", "toml"]
+
+[dependencies]
+anyhow = "1.0"
+serde = { version = "1.0", features = ["derive"] }
+
+[[servers]]
+name = "alpha"
+tls = { cert = "a.pem" }
+
+[[servers]]
+name = "beta"
+"#
+        );
+
+        let options = JsonOptions {
+            document_order: true,
+            ..Default::default()
+        };
+        let json = render_document(&doc, Syntax::Json, &options);
+        let back = parse_document(&json, Syntax::Json).expect("json output should convert back");
+        assert_eq!(back.to_string(), doc.to_string());
+        assert!(render_document(&doc, Syntax::Yaml, &JsonOptions::default())
+            .starts_with("package:\n  name: tomato\n"));
+
+        assert!(parse_document("[1, 2]", Syntax::Json).is_err());
+        assert!(parse_document("a: null", Syntax::Yaml).is_err());
+        assert_eq!(Syntax::from_path("config/app.yml"), Some(Syntax::Yaml));
+        assert_eq!(Syntax::from_path("Makefile"), None);
+    }
+}
@@ -0,0 +1,286 @@
+//! Compare two documents by the data they hold rather than by their text, so that
+//! reordered keys and reformatted values don't show up as differences.
+
+use toml_edit::{Document, Item, TableLike};
+
+use crate::json::{json_to_string, to_json, JsonOptions};
+use crate::merge::same;
+use crate::{KeySegment, Keyspec};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// How a key differs between two documents.
+pub enum Kind {
+    Added,
+    Removed,
+    Changed,
+    /// The data is the same, but the formatting or comments aren't
+    Formatting,
+}
+
+impl Kind {
+    /// The name of this kind of difference, as written in json output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Added => "added",
+            Kind::Removed => "removed",
+            Kind::Changed => "changed",
+            Kind::Formatting => "formatting",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// One key that differs between two documents, with its value in each.
+pub struct Difference {
+    pub kind: Kind,
+    pub key: Keyspec,
+    pub old: Item,
+    pub new: Item,
+}
+
+/// Find every key that differs between the two documents. Tables are compared key by
+/// key, whether they're written inline or not, as are arrays of tables of the same
+/// length. Anything else that differs is reported whole. Differences in formatting and
+/// comments alone are reported only if asked for.
+pub fn diff_documents(left: &Document, right: &Document, formatting: bool) -> Vec<Difference> {
+    let mut differ = Differ {
+        path: Vec::new(),
+        found: Vec::new(),
+        formatting,
+    };
+    differ.tables(left.as_table(), right.as_table());
+    differ.found
+}
+
+/// The state of a walk through two documents at once.
+struct Differ {
+    path: Vec<KeySegment>,
+    found: Vec<Difference>,
+    formatting: bool,
+}
+
+impl Differ {
+    fn record(&mut self, kind: Kind, old: &Item, new: &Item) {
+        self.found.push(Difference {
+            kind,
+            key: Keyspec {
+                subkeys: self.path.clone(),
+            },
+            old: old.clone(),
+            new: new.clone(),
+        });
+    }
+
+    fn tables(&mut self, left: &dyn TableLike, right: &dyn TableLike) {
+        for (k, old) in left.iter() {
+            self.path.push(KeySegment::Name(k.to_string()));
+            match right.get(k) {
+                Some(new) => self.items(old, new),
+                None => self.record(Kind::Removed, old, &Item::None),
+            }
+            self.path.pop();
+        }
+        for (k, new) in right.iter().filter(|(k, _)| !left.contains_key(k)) {
+            self.path.push(KeySegment::Name(k.to_string()));
+            self.record(Kind::Added, &Item::None, new);
+            self.path.pop();
+        }
+    }
+
+    fn items(&mut self, old: &Item, new: &Item) {
+        if let (Some(left), Some(right)) = (old.as_table_like(), new.as_table_like()) {
+            if self.formatting && !same_layout(old, new) {
+                self.record(Kind::Formatting, old, new);
+            }
+            return self.tables(left, right);
+        }
+        if let (Item::ArrayOfTables(left), Item::ArrayOfTables(right)) = (old, new) {
+            if left.len() == right.len() {
+                for (idx, (l, r)) in left.iter().zip(right.iter()).enumerate() {
+                    self.path.push(KeySegment::Index(idx));
+                    self.items(&Item::Table(l.clone()), &Item::Table(r.clone()));
+                    self.path.pop();
+                }
+                return;
+            }
+        }
+        if !same(old, new) {
+            self.record(Kind::Changed, old, new);
+        } else if self.formatting && old.to_string() != new.to_string() {
+            self.record(Kind::Formatting, old, new);
+        }
+    }
+}
+
+/// True if two tables are written the same way: both inline or both not, with the
+/// same comments and whitespace around their headers.
+fn same_layout(old: &Item, new: &Item) -> bool {
+    match (old, new) {
+        (Item::Table(left), Item::Table(right)) => left.decor() == right.decor(),
+        (Item::Value(left), Item::Value(right)) => left.decor() == right.decor(),
+        _ => false,
+    }
+}
+
+/// A value written inline as toml, without the whitespace and comments around it.
+fn inline_text(item: &Item) -> String {
+    match item.clone().into_value() {
+        Ok(mut value) => {
+            value.decor_mut().clear();
+            value.to_string()
+        }
+        Err(_) => "".to_string(),
+    }
+}
+
+/// Describe the differences one per line, with `+` for added keys, `-` for removed
+/// keys, `~` for changed keys, and `=` for keys whose formatting alone changed.
+pub fn format_text(diffs: &[Difference]) -> String {
+    diffs
+        .iter()
+        .map(|d| match d.kind {
+            Kind::Added => format!("+ {} = {}", d.key, inline_text(&d.new)),
+            Kind::Removed => format!("- {} = {}", d.key, inline_text(&d.old)),
+            Kind::Changed => format!(
+                "~ {} = {} -> {}",
+                d.key,
+                inline_text(&d.old),
+                inline_text(&d.new)
+            ),
+            Kind::Formatting => format!("= {} (formatting)", d.key),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Describe the differences as a json array of objects.
+pub fn format_json(diffs: &[Difference], options: &JsonOptions) -> String {
+    let report: Vec<serde_json::Value> = diffs
+        .iter()
+        .map(|d| {
+            let mut obj = serde_json::Map::new();
+            obj.insert("change".to_string(), d.kind.name().into());
+            obj.insert("key".to_string(), d.key.to_string().into());
+            if !d.old.is_none() {
+                obj.insert("old".to_string(), to_json(&d.old, options));
+            }
+            if !d.new.is_none() {
+                obj.insert("new".to_string(), to_json(&d.new, options));
+            }
+            serde_json::Value::Object(obj)
+        })
+        .collect();
+    json_to_string(serde_json::Value::Array(report), options)
+}
+
+/// Write the differences as a list of patch operations that turn the first document into
+/// the second: `{"op": "add", "path": "db.user", "value": "tomato"}` and the like, with
+/// `remove` and `replace` as well. Differences in formatting alone have no operation.
+/// Datetimes are always typed, so they come back as datetimes.
+pub fn format_patch(diffs: &[Difference], options: &JsonOptions) -> String {
+    let options = JsonOptions {
+        typed_datetimes: true,
+        ..*options
+    };
+    let ops: Vec<serde_json::Value> = diffs
+        .iter()
+        .filter_map(|d| {
+            let path = d.key.to_string();
+            match d.kind {
+                Kind::Added => Some(serde_json::json!({
+                    "op": "add", "path": path, "value": to_json(&d.new, &options)
+                })),
+                Kind::Removed => Some(serde_json::json!({ "op": "remove", "path": path })),
+                Kind::Changed => Some(serde_json::json!({
+                    "op": "replace", "path": path, "value": to_json(&d.new, &options)
+                })),
+                Kind::Formatting => None,
+            }
+        })
+        .collect();
+    json_to_string(serde_json::Value::Array(ops), &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: &str = r#"
+title = "tomato"
+ports = [80, 443]
+
+[db]
+url = "sqlite://"
+pool = 5 # connections
+
+[[servers]]
+name = "alpha"
+"#;
+
+    const RIGHT: &str = r#"
+ports = [80, 8443]
+title = 'tomato'
+db = { pool = 5, user = "tomato" }
+
+[[servers]]
+name = "beta"
+"#;
+
+    fn diff(formatting: bool) -> Vec<Difference> {
+        let left = LEFT.parse::<Document>().unwrap();
+        let right = RIGHT.parse::<Document>().unwrap();
+        diff_documents(&left, &right, formatting)
+    }
+
+    #[test]
+    fn semantic_differences() {
+        let diffs = diff(false);
+        assert_eq!(
+            format_text(&diffs),
+            r#"~ ports = [80, 443] -> [80, 8443]
+- db.url = "sqlite://"
++ db.user = "tomato"
+~ servers.0.name = "alpha" -> "beta""#
+        );
+        assert_eq!(
+            format_json(&diffs[1..3], &JsonOptions::default()),
+            r#"[{"change":"removed","key":"db.url","old":"sqlite://"},{"change":"added","key":"db.user","new":"tomato"}]"#
+        );
+        assert_eq!(
+            format_patch(&diffs, &JsonOptions::default()),
+            r#"[{"op":"replace","path":"ports","value":[80,8443]},{"op":"remove","path":"db.url"},{"op":"add","path":"db.user","value":"tomato"},{"op":"replace","path":"servers.0.name","value":"beta"}]"#
+        );
+
+        let formatted: Vec<String> = diff(true)
+            .iter()
+            .filter(|d| d.kind == Kind::Formatting)
+            .map(|d| d.key.to_string())
+            .collect();
+        assert_eq!(formatted, ["title", "db", "db.pool"]);
+
+        let left = LEFT.parse::<Document>().unwrap();
+        assert!(diff_documents(&left, &left.clone(), true).is_empty());
+    }
+
+    #[test]
+    fn patches_quote_odd_keys() {
+        let mut left = "[servers.\"10.0.0.1\"]\nport = 22\n\n[codes]\n404 = \"gone\"\n"
+            .parse::<Document>()
+            .unwrap();
+        let right = "[servers.\"10.0.0.1\"]\nport = 2222\n\n[codes]\n404 = \"lost\"\n\"a.b\" = 1\n"
+            .parse::<Document>()
+            .unwrap();
+        let diffs = diff_documents(&left, &right, false);
+        assert_eq!(
+            format_text(&diffs),
+            r#"~ servers."10.0.0.1".port = 22 -> 2222
+~ codes."404" = "gone" -> "lost"
++ codes."a.b" = 1"#
+        );
+
+        let patch = format_patch(&diffs, &JsonOptions::default());
+        let ops = crate::patch::parse_patch(&patch).unwrap();
+        crate::patch::apply_patch(&mut left, &ops).unwrap();
+        assert!(diff_documents(&left, &right, false).is_empty());
+    }
+}
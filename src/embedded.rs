@@ -0,0 +1,178 @@
+//! Find toml embedded in other files, and put it back after editing without
+//! touching anything around it. Two kinds of embedding are understood: `+++`
+//! front matter at the top of a markdown file, as Hugo and Zola use, and a
+//! ```` ```cargo ```` block in the `//!` doc comment of a cargo script. Front matter is
+//! only found if the file starts with it, and the doc comment only if every line of it
+//! starts with `//!`, without indentation.
+
+/// The line that opens and closes front matter.
+const FRONT_MATTER_FENCE: &str = "+++";
+/// The comment marker on each line of a cargo script manifest.
+const DOC_COMMENT: &str = "//!";
+
+#[derive(Clone, Debug)]
+/// The file some toml was found in, with the toml itself taken out.
+pub struct Embedded {
+    /// Everything before the toml, including the line that opens it.
+    before: String,
+    /// Everything after the toml, including the line that closes it.
+    after: String,
+    /// What goes in front of each line of the toml, if it lives in comments.
+    prefix: Option<String>,
+}
+
+impl Embedded {
+    /// Find the toml in the text of a host file, responding with the host and the toml.
+    pub fn split(text: &str) -> Result<(Embedded, String), anyhow::Error> {
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        if let Some(first) = lines.first() {
+            if first.trim_end() == FRONT_MATTER_FENCE {
+                let end = lines
+                    .iter()
+                    .skip(1)
+                    .position(|line| line.trim_end() == FRONT_MATTER_FENCE)
+                    .map(|idx| idx + 1)
+                    .ok_or_else(|| anyhow::anyhow!("the +++ front matter is never closed"))?;
+                let embedded = Embedded {
+                    before: lines[..1].concat(),
+                    after: lines[end..].concat(),
+                    prefix: None,
+                };
+                return Ok((embedded, lines[1..end].concat()));
+            }
+        }
+
+        let start = lines
+            .iter()
+            .position(|line| doc_comment_text(line).map(str::trim) == Some("```cargo"))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "no embedded toml found; expected +++ front matter or a //! ```cargo block"
+                )
+            })?;
+        // The block ends at the closing fence, which must come before the doc comment does.
+        let mut end = None;
+        for (idx, line) in lines.iter().enumerate().skip(start + 1) {
+            match doc_comment_text(line) {
+                Some(text) if text.trim() == "```" => {
+                    end = Some(idx);
+                    break;
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        let end = end.ok_or_else(|| anyhow::anyhow!("the //! ```cargo block is never closed"))?;
+
+        let body = &lines[start + 1..end];
+        // Keep the space after the comment marker if every line has one.
+        let spaced = body.iter().all(|line| {
+            let text = doc_comment_text(line).unwrap_or_default();
+            text.trim().is_empty() || text.starts_with(' ')
+        });
+        let prefix = if spaced {
+            format!("{DOC_COMMENT} ")
+        } else {
+            DOC_COMMENT.to_string()
+        };
+        let toml: String = body
+            .iter()
+            .map(|line| {
+                let text = doc_comment_text(line).unwrap_or_default();
+                if spaced {
+                    text.strip_prefix(' ').unwrap_or(text)
+                } else {
+                    text
+                }
+            })
+            .collect();
+        let embedded = Embedded {
+            before: lines[..=start].concat(),
+            after: lines[end..].concat(),
+            prefix: Some(prefix),
+        };
+        Ok((embedded, toml))
+    }
+
+    /// Put the toml back into the host file.
+    pub fn join(&self, toml: &str) -> String {
+        let mut text = self.before.clone();
+        for line in toml.split_inclusive('\n') {
+            match self.prefix {
+                Some(_) if line.trim().is_empty() => {
+                    text.push_str(DOC_COMMENT);
+                    text.push_str(line.trim_start_matches([' ', '\t']));
+                }
+                Some(ref prefix) => {
+                    text.push_str(prefix);
+                    text.push_str(line);
+                }
+                None => text.push_str(line),
+            }
+        }
+        if !toml.is_empty() && !toml.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&self.after);
+        text
+    }
+}
+
+/// The text of a `//!` comment line after the marker, or None if it isn't one.
+fn doc_comment_text(line: &str) -> Option<&str> {
+    line.strip_prefix(DOC_COMMENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml_edit::Document;
+
+    fn edit(text: &str) -> String {
+        let (host, toml) = Embedded::split(text).expect("test text should hold toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("embedded toml should be valid");
+        assert_eq!(host.join(&doc.to_string()), text);
+        doc["title"] = toml_edit::value("Edited");
+        host.join(&doc.to_string())
+    }
+
+    #[test]
+    fn front_matter() {
+        let text =
+            "+++\ntitle = \"Hello\"\n\ndraft = true # for now\n+++\n\n# Hello\n\n+++ not toml\n";
+        assert_eq!(
+            edit(text),
+            "+++\ntitle = \"Edited\"\n\ndraft = true # for now\n+++\n\n# Hello\n\n+++ not toml\n"
+        );
+        assert!(Embedded::split("+++\ntitle = 1\n").is_err());
+    }
+
+    #[test]
+    fn cargo_script() {
+        let text = r#"#!/usr/bin/env cargo
+//! A script.
+//!
+//! ```cargo
+//! [package]
+//! edition = "2021"
+//!
+//! [dependencies]
+//! anyhow = "1.0"
+//! ```
+
+fn main() {}
+"#;
+        let (host, toml) = Embedded::split(text).unwrap();
+        assert_eq!(
+            toml,
+            "[package]\nedition = \"2021\"\n\n[dependencies]\nanyhow = \"1.0\"\n"
+        );
+        assert_eq!(host.join(&toml), text);
+        assert!(edit(text).contains("//! ```cargo\n//! title = \"Edited\"\n//! [package]\n"));
+
+        assert!(Embedded::split("//! ```cargo\n//! a = 1\nfn main() {}\n").is_err());
+        assert!(Embedded::split("fn main() {}\n").is_err());
+    }
+}
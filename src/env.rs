@@ -0,0 +1,162 @@
+/// Implement serialization into dotenv files and shell `export` lines.
+use toml_edit::{Item, Value};
+
+use crate::bash::{flatten, variable_name, BashOptions};
+use crate::Keyspec;
+
+/// Format a toml_edit::Item as `KEY=value` lines in dotenv syntax, or as `export KEY=value`
+/// lines for a shell to source. Tables and arrays are flattened into one variable per
+/// value, named the same way flattened bash variables are.
+pub fn format_env(item: &Item, name: &str, options: &BashOptions, export: bool) -> String {
+    flatten(item, name, options, true)
+        .into_iter()
+        .map(|(name, v)| {
+            let text = env_text(&v);
+            if export {
+                format!("export {name}={}", export_quote(&text))
+            } else {
+                format!("{name}={}", dotenv_quote(&text))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Format the results of several lookups as env lines, named after their full keys.
+pub fn format_env_assignments(
+    found: &[(Keyspec, Item)],
+    options: &BashOptions,
+    export: bool,
+) -> String {
+    found
+        .iter()
+        .map(|(key, item)| format_env(item, &variable_name(key), options, export))
+        .filter(|lines| !lines.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The text of a scalar value. Environment variables are all strings, so booleans
+/// are spelled out rather than written as 1 or 0.
+fn env_text(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.value().clone(),
+        Value::Integer(i) => i.value().to_string(),
+        Value::Float(f) => f.value().to_string(),
+        Value::Boolean(b) => b.value().to_string(),
+        Value::Datetime(dt) => dt.value().to_string(),
+        // Flattening splits arrays and tables into scalars before they get here.
+        Value::Array(_) | Value::InlineTable(_) => "".to_string(),
+    }
+}
+
+/// True if the text means the same thing to every shell and dotenv parser without quotes.
+fn is_plain(text: &str) -> bool {
+    text.chars().all(|c| {
+        c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ':' | '@' | '+' | ',')
+    })
+}
+
+/// Quote a value for a dotenv file. Dotenv parsers disagree about most things, but
+/// they agree that nothing inside single quotes is special, so that's what we use
+/// when we can. Values holding single quotes or line breaks are double-quoted, with
+/// backslash escapes and `$` escaped against interpolation.
+fn dotenv_quote(text: &str) -> String {
+    if is_plain(text) {
+        return text.to_string();
+    }
+    if !text.contains(['\'', '\n', '\r']) {
+        return format!("'{text}'");
+    }
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str(r"\$"),
+            '\n' => quoted.push_str(r"\n"),
+            '\r' => quoted.push_str(r"\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quote a value for a shell to source. Any POSIX shell might source these, so
+/// everything goes in single quotes, with each single quote written as `'\''`. Line
+/// breaks and other control characters are taken literally inside them.
+fn export_quote(text: &str) -> String {
+    if is_plain(text) {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_key;
+    use std::str::FromStr;
+    use toml_edit::Document;
+
+    #[test]
+    fn env_output() {
+        let toml = r#"
+[app]
+name = "tomato"
+debug = false
+greeting = "hello, $USER"
+motto = "it's \"fine\"\nreally"
+hosts = ["a.example.com", "b.example.com"]
+
+[app.db]
+url = "postgres://db:5432/app"
+pool = 5
+"#;
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let key = Keyspec::from_str("app").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+
+        assert_eq!(
+            format_env(&item, "app", &BashOptions::default(), false),
+            r#"APP_NAME=tomato
+APP_DEBUG=false
+APP_GREETING='hello, $USER'
+APP_MOTTO="it's \"fine\"\nreally"
+APP_HOSTS_0=a.example.com
+APP_HOSTS_1=b.example.com
+APP_DB_URL=postgres://db:5432/app
+APP_DB_POOL=5"#
+        );
+
+        let options = BashOptions {
+            prefix: Some("".to_string()),
+            ..Default::default()
+        };
+        let key = Keyspec::from_str("app.db").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+        assert_eq!(
+            format_env(&item, "db", &options, true),
+            "export URL=postgres://db:5432/app\nexport POOL=5"
+        );
+
+        let key = Keyspec::from_str("app.motto").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+        assert_eq!(
+            format_env(&item, "motto", &BashOptions::default(), true),
+            "export MOTTO='it'\\''s \"fine\"\nreally'"
+        );
+        assert_eq!(format_env(&item, "", &options, true), "");
+
+        let keys = crate::KeyList::from_str("app.name,app.missing").unwrap();
+        let found = crate::get_keys(&mut doc, &keys, None).unwrap();
+        assert_eq!(
+            format_env_assignments(&found, &BashOptions::default(), false),
+            "APP_NAME=tomato\nAPP_MISSING="
+        );
+    }
+}
@@ -0,0 +1,207 @@
+/// Add to and subtract from numbers in a document, writing the result the same way the
+/// original number was written: in the same base, with the same digit grouping, and
+/// to the same number of decimal places.
+use std::str::FromStr;
+use toml_edit::{Document, Item, Value};
+
+use crate::{get_key, set_key, Keyspec};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// An amount to add to a number.
+pub enum Amount {
+    Integer(i64),
+    /// A float, with the number of decimal places it was written with.
+    Float(f64, usize),
+}
+
+impl FromStr for Amount {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        if let Ok(n) = input.parse::<i64>() {
+            return Ok(Amount::Integer(n));
+        }
+        match input.parse::<f64>() {
+            Ok(f) if f.is_finite() => Ok(Amount::Float(f, decimal_places(input))),
+            _ => anyhow::bail!("{input} is not a number"),
+        }
+    }
+}
+
+impl Amount {
+    /// The same amount with the opposite sign.
+    pub fn negated(self) -> Amount {
+        match self {
+            Amount::Integer(n) => Amount::Integer(n.saturating_neg()),
+            Amount::Float(f, places) => Amount::Float(-f, places),
+        }
+    }
+}
+
+/// Pick the amount to add out of the arguments given after the key: the one given with
+/// `--by`, or else the first argument if it's a number, or else 1. Responds with the
+/// amount and the files that follow it.
+pub fn split_amount(by: Option<Amount>, args: &[String]) -> (Amount, &[String]) {
+    match (by, args.split_first()) {
+        (Some(amount), _) => (amount, args),
+        (None, Some((first, rest))) => match Amount::from_str(first) {
+            Ok(amount) => (amount, rest),
+            Err(_) => (Amount::Integer(1), args),
+        },
+        (None, None) => (Amount::Integer(1), args),
+    }
+}
+
+/// Add the amount to the number at the key, responding with the original value. A
+/// missing key is an error unless `create` is set, in which case it starts at 0.
+/// Integers stay integers, so adding a fractional amount to one is an error.
+pub fn increment(
+    toml: &mut Document,
+    key: &Keyspec,
+    amount: Amount,
+    create: bool,
+) -> Result<Item, anyhow::Error> {
+    let value = match (get_key(toml, key)?, amount) {
+        (Item::None, _) if !create => {
+            anyhow::bail!("key {key} not found; pass --create to start it at 0")
+        }
+        (Item::None, Amount::Integer(n)) => Value::from(n),
+        (Item::None, Amount::Float(f, places)) => format_float(f, places)?,
+        (Item::Value(Value::Integer(current)), Amount::Integer(n)) => {
+            let result = current
+                .value()
+                .checked_add(n)
+                .ok_or_else(|| anyhow::anyhow!("adding {n} to {key} doesn't fit in an integer"))?;
+            match current.as_repr().and_then(|r| r.as_raw().as_str()) {
+                Some(raw) => format_integer(raw, result)?,
+                None => Value::from(result),
+            }
+        }
+        (Item::Value(Value::Integer(_)), Amount::Float(f, _)) => {
+            anyhow::bail!("key {key} holds an integer, so {f} can't be added to it")
+        }
+        (Item::Value(Value::Float(current)), amount) => {
+            let raw = current.as_repr().and_then(|r| r.as_raw().as_str());
+            let (n, places) = match amount {
+                Amount::Integer(n) => (n as f64, 0),
+                Amount::Float(f, places) => (f, places),
+            };
+            let result = current.value() + n;
+            match raw {
+                // Floats written with exponents, or as inf or nan, aren't worth imitating.
+                Some(raw) if raw.contains('.') && !raw.contains(['e', 'E']) => {
+                    format_float(result, decimal_places(raw).max(places))?
+                }
+                _ => Value::from(result),
+            }
+        }
+        _ => anyhow::bail!("key {key} does not hold a number"),
+    };
+    set_key(toml, key, &value)
+}
+
+/// Write the integer the way the original was written: in hex, octal, or binary with
+/// the same prefix, case, and zero padding, and with underscores between groups of the
+/// same size. Negative numbers can't be written in other bases, so they're decimal.
+fn format_integer(raw: &str, n: i64) -> Result<Value, anyhow::Error> {
+    let unsigned = raw.trim_start_matches(['+', '-']);
+    let (prefix, digits) = match unsigned.get(..2) {
+        Some(p @ ("0x" | "0o" | "0b")) if n >= 0 => (p, &unsigned[2..]),
+        Some("0x" | "0o" | "0b") => ("", ""),
+        _ => ("", unsigned),
+    };
+    let mut text = match prefix {
+        "0x" if digits.chars().any(|c| c.is_ascii_uppercase()) => format!("{n:X}"),
+        "0x" => format!("{n:x}"),
+        "0o" => format!("{n:o}"),
+        "0b" => format!("{n:b}"),
+        _ => n.unsigned_abs().to_string(),
+    };
+    if !prefix.is_empty() {
+        let width = digits.chars().filter(|c| *c != '_').count();
+        text = format!("{text:0>width$}");
+    }
+    if let Some((_, group)) = digits.rsplit_once('_') {
+        text = group_digits(&text, group.len());
+    }
+    let sign = match (n < 0, raw.starts_with('+')) {
+        (true, _) => "-",
+        (false, true) => "+",
+        (false, false) => "",
+    };
+    Ok(format!("{sign}{prefix}{text}").parse::<Value>()?)
+}
+
+/// Write the float with exactly this many decimal places.
+fn format_float(f: f64, places: usize) -> Result<Value, anyhow::Error> {
+    if places == 0 {
+        return Ok(Value::from(f));
+    }
+    Ok(format!("{f:.places$}").parse::<Value>()?)
+}
+
+/// Put an underscore between each group of digits, counting from the right.
+fn group_digits(digits: &str, size: usize) -> String {
+    if size == 0 {
+        return digits.to_string();
+    }
+    let chars: Vec<char> = digits.chars().collect();
+    let mut grouped = String::new();
+    for (idx, c) in chars.iter().enumerate() {
+        if idx > 0 && (chars.len() - idx).is_multiple_of(size) {
+            grouped.push('_');
+        }
+        grouped.push(*c);
+    }
+    grouped
+}
+
+/// How many digits a number is written with after its decimal point.
+fn decimal_places(text: &str) -> usize {
+    text.split_once('.')
+        .map(|(_, fraction)| fraction.chars().filter(|c| c.is_ascii_digit()).count())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bump(toml: &str, key: &str, amount: &str) -> Result<String, anyhow::Error> {
+        let mut doc = toml.parse::<Document>()?;
+        let key = Keyspec::from_str(key)?;
+        increment(&mut doc, &key, Amount::from_str(amount)?, true)?;
+        Ok(doc.to_string())
+    }
+
+    #[test]
+    fn increments_keep_their_looks() {
+        assert_eq!(
+            bump("n = 41 # build\n", "n", "1").unwrap(),
+            "n = 42 # build\n"
+        );
+        assert_eq!(bump("n = 0x00FF\n", "n", "1").unwrap(), "n = 0x0100\n");
+        assert_eq!(bump("n = 0o17\n", "n", "1").unwrap(), "n = 0o20\n");
+        assert_eq!(bump("n = 0b0111\n", "n", "1").unwrap(), "n = 0b1000\n");
+        assert_eq!(bump("n = 999_999\n", "n", "1").unwrap(), "n = 1_000_000\n");
+        assert_eq!(bump("n = +3\n", "n", "-5").unwrap(), "n = -2\n");
+        assert_eq!(bump("n = 0x1\n", "n", "-2").unwrap(), "n = -1\n");
+        assert_eq!(bump("f = 1.50\n", "f", "1").unwrap(), "f = 2.50\n");
+        assert_eq!(bump("f = 0.1\n", "f", "0.2").unwrap(), "f = 0.3\n");
+        assert_eq!(bump("f = 1.5\n", "f", "0.25").unwrap(), "f = 1.75\n");
+        assert_eq!(
+            bump("a = 1\n", "b.c", "5").unwrap(),
+            "a = 1\nb = { c = 5 }\n"
+        );
+
+        assert!(bump("n = 1\n", "n", "0.5").is_err());
+        assert!(bump("s = \"1\"\n", "s", "1").is_err());
+        assert!(bump("n = 9223372036854775807\n", "n", "1").is_err());
+        assert!(Amount::from_str("lots").is_err());
+
+        let mut doc = "a = 1\n".parse::<Document>().unwrap();
+        let key = Keyspec::from_str("b").unwrap();
+        assert!(increment(&mut doc, &key, Amount::Integer(1), false).is_err());
+    }
+}
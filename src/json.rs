@@ -1,30 +1,47 @@
 /// Implement json serialization for the toml_edit data structures
-use toml_edit::{Item, Value};
+use serde::Serialize;
+use toml_edit::{Document, Item, Value};
 
-/// Turn a toml_edit::Item into a json Value
-pub fn to_json(item: &Item) -> serde_json::Value {
+/// The key used to mark a typed datetime, e.g. `{"$datetime": "1979-05-27T07:32:00Z"}`.
+pub const DATETIME_KEY: &str = "$datetime";
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Options for json output.
+pub struct JsonOptions {
+    /// Pretty-print with this many spaces of indentation; None for a single line.
+    pub indent: Option<usize>,
+    /// Keep object keys in document order instead of sorting them.
+    pub document_order: bool,
+    /// Write datetimes as `{"$datetime": "..."}` objects instead of plain strings, so
+    /// they can be told apart from strings that happen to look like datetimes.
+    pub typed_datetimes: bool,
+}
+
+/// Turn a toml_edit::Item into a json Value, encoding datetimes as the options ask.
+pub fn to_json(item: &Item, options: &JsonOptions) -> serde_json::Value {
     match item {
         Item::None => serde_json::Value::Null,
-        Item::Value(value) => value_to_json(value.clone()),
-        Item::Table(table) => table_to_json(table),
+        Item::Value(value) => value_to_json(value.clone(), options),
+        Item::Table(table) => table_to_json(table, options),
         Item::ArrayOfTables(aot) => {
-            let items: Vec<serde_json::Value> = aot.iter().map(table_to_json).collect();
+            let items: Vec<serde_json::Value> =
+                aot.iter().map(|t| table_to_json(t, options)).collect();
             serde_json::Value::Array(items)
         }
     }
 }
 
 /// Turn a toml_edit::Table structure into a json object
-pub fn table_to_json(table: &toml_edit::Table) -> serde_json::Value {
+pub fn table_to_json(table: &toml_edit::Table, options: &JsonOptions) -> serde_json::Value {
     let obj: serde_json::Map<String, serde_json::Value> = table
         .iter()
-        .map(|(k, v)| (k.to_string(), to_json(v)))
+        .map(|(k, v)| (k.to_string(), to_json(v, options)))
         .collect();
     serde_json::Value::Object(obj)
 }
 
 /// Turn a toml_edit::Value into a serde_json::Value
-pub fn value_to_json(v: Value) -> serde_json::Value {
+pub fn value_to_json(v: Value, options: &JsonOptions) -> serde_json::Value {
     match v {
         Value::String(s) => serde_json::Value::String(s.into_value()),
         Value::Integer(i) => serde_json::Value::Number(i.into_value().into()),
@@ -37,26 +54,171 @@ pub fn value_to_json(v: Value) -> serde_json::Value {
             }
         }
         Value::Boolean(b) => serde_json::Value::Bool(b.into_value()),
-        Value::Datetime(dt) => serde_json::Value::String(dt.into_value().to_string()),
+        Value::Datetime(dt) => {
+            let text = serde_json::Value::String(dt.into_value().to_string());
+            if options.typed_datetimes {
+                let mut obj = serde_json::Map::new();
+                obj.insert(DATETIME_KEY.to_string(), text);
+                serde_json::Value::Object(obj)
+            } else {
+                text
+            }
+        }
         Value::Array(array) => {
-            let items: Vec<serde_json::Value> =
-                array.iter().map(|xs| value_to_json(xs.clone())).collect();
+            let items: Vec<serde_json::Value> = array
+                .iter()
+                .map(|xs| value_to_json(xs.clone(), options))
+                .collect();
             serde_json::Value::Array(items)
         }
         Value::InlineTable(table) => {
             let obj: serde_json::Map<String, serde_json::Value> = table
                 .iter()
-                .map(|(k, v)| (k.to_string(), value_to_json(v.clone())))
+                .map(|(k, v)| (k.to_string(), value_to_json(v.clone(), options)))
                 .collect();
             serde_json::Value::Object(obj)
         }
     }
 }
 
+/// Recursively sort the keys of every object in this json value.
+fn sort_keys(json: serde_json::Value) -> serde_json::Value {
+    match json {
+        serde_json::Value::Object(obj) => {
+            let mut entries: Vec<(String, serde_json::Value)> = obj.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, sort_keys(v)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(sort_keys).collect())
+        }
+        other => other,
+    }
+}
+
+/// Serialize a json value to a string, sorting and indenting as the options ask.
+pub fn json_to_string(json: serde_json::Value, options: &JsonOptions) -> String {
+    let json = if options.document_order {
+        json
+    } else {
+        sort_keys(json)
+    };
+    match options.indent {
+        None => json.to_string(),
+        Some(width) => {
+            let indent = " ".repeat(width);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            let mut buf = Vec::new();
+            let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+            json.serialize(&mut serializer)
+                .expect("serializing a json value to memory cannot fail");
+            String::from_utf8(buf).expect("serde_json always writes utf-8")
+        }
+    }
+}
+
+/// If this json value is a typed datetime, respond with the datetime text inside it.
+fn datetime_text(json: &serde_json::Value) -> Option<&str> {
+    match json.as_object() {
+        Some(obj) if obj.len() == 1 => obj.get(DATETIME_KEY).and_then(|v| v.as_str()),
+        _ => None,
+    }
+}
+
+/// Turn a serde_json::Value into a toml_edit::Value. Json has nulls and toml does
+/// not, so those are an error. Typed datetimes written by `--typed-datetimes` become
+/// toml datetimes again; plain strings stay strings.
+pub fn json_to_value(json: &serde_json::Value) -> Result<Value, anyhow::Error> {
+    if let Some(text) = datetime_text(json) {
+        let dt = text
+            .parse::<toml_edit::Datetime>()
+            .map_err(|e| anyhow::anyhow!("{text} is not a valid toml datetime: {e}"))?;
+        return Ok(Value::from(dt));
+    }
+    let value = match json {
+        serde_json::Value::Null => anyhow::bail!("toml has no way to represent null"),
+        serde_json::Value::Bool(b) => Value::from(*b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::from(i)
+            } else if let Some(f) = n.as_f64() {
+                Value::from(f)
+            } else {
+                anyhow::bail!("{n} is out of range for a toml number");
+            }
+        }
+        serde_json::Value::String(s) => Value::from(s.as_str()),
+        serde_json::Value::Array(items) => {
+            let array: toml_edit::Array =
+                items.iter().map(json_to_value).collect::<Result<_, _>>()?;
+            Value::Array(array)
+        }
+        serde_json::Value::Object(obj) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (k, v) in obj {
+                table.insert(k, json_to_value(v)?);
+            }
+            Value::InlineTable(table)
+        }
+    };
+    Ok(value)
+}
+
+/// Turn a serde_json::Value into a toml_edit::Item, reversing `to_json`. Objects become
+/// tables and arrays of objects become arrays of tables; everything nested inside an
+/// array is written inline.
+pub fn json_to_item(json: &serde_json::Value) -> Result<Item, anyhow::Error> {
+    let is_table = |v: &serde_json::Value| v.is_object() && datetime_text(v).is_none();
+    match json {
+        serde_json::Value::Object(obj) if is_table(json) => {
+            let mut table = toml_edit::Table::new();
+            for (k, v) in obj {
+                table.insert(k, json_to_item(v)?);
+            }
+            // A table holding nothing but other tables doesn't need a header of its own.
+            let only_tables = table
+                .iter()
+                .all(|(_, v)| v.is_table() || v.is_array_of_tables());
+            table.set_implicit(!table.is_empty() && only_tables);
+            Ok(Item::Table(table))
+        }
+        serde_json::Value::Array(items) if !items.is_empty() && items.iter().all(is_table) => {
+            let mut aot = toml_edit::ArrayOfTables::new();
+            for v in items {
+                if let Item::Table(table) = json_to_item(v)? {
+                    aot.push(table);
+                }
+            }
+            Ok(Item::ArrayOfTables(aot))
+        }
+        _ => Ok(Item::Value(json_to_value(json)?)),
+    }
+}
+
+/// Turn a json object into a whole toml document.
+pub fn json_to_document(json: &serde_json::Value) -> Result<Document, anyhow::Error> {
+    match json_to_item(json)? {
+        Item::Table(mut table) => {
+            table.set_implicit(false);
+            Ok(Document::from(table))
+        }
+        _ => anyhow::bail!("only a json object can be turned into a toml document"),
+    }
+}
+
 /// Given any toml_edit::Item, serialize it to a valid json string
 pub fn format_json(item: &Item) -> String {
-    let json = to_json(item);
-    json.to_string()
+    format_json_with(item, &JsonOptions::default())
+}
+
+/// Given any toml_edit::Item, serialize it to a json string shaped by the options.
+pub fn format_json_with(item: &Item, options: &JsonOptions) -> String {
+    json_to_string(to_json(item, options), options)
 }
 
 #[cfg(test)]
@@ -64,7 +226,6 @@ mod tests {
     use super::*;
     use crate::{get_key, Keyspec};
     use std::str::FromStr;
-    use toml_edit::Document;
 
     #[test]
     fn json_output() {
@@ -108,4 +269,48 @@ mod tests {
         println!("{json}");
         assert_eq!(json, include_str!("../fixtures/sample.json").trim());
     }
+
+    #[test]
+    fn json_options() {
+        let toml = r#"
+zebra = 1
+when = 1979-05-27T07:32:00Z
+[apple]
+day = 1979-05-27
+list = [2, 1]
+"#;
+        let doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+
+        let options = JsonOptions {
+            document_order: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_json_with(doc.as_item(), &options),
+            r#"{"zebra":1,"when":"1979-05-27T07:32:00Z","apple":{"day":"1979-05-27","list":[2,1]}}"#
+        );
+
+        let options = JsonOptions {
+            typed_datetimes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_json_with(doc.as_item(), &options),
+            r#"{"apple":{"day":{"$datetime":"1979-05-27"},"list":[2,1]},"when":{"$datetime":"1979-05-27T07:32:00Z"},"zebra":1}"#
+        );
+
+        let options = JsonOptions {
+            indent: Some(4),
+            ..Default::default()
+        };
+        let key = Keyspec::from_str("apple").unwrap();
+        let mut doc = doc;
+        let item = get_key(&mut doc, &key).expect("expected to find key apple");
+        assert_eq!(
+            format_json_with(&item, &options),
+            "{\n    \"day\": \"1979-05-27\",\n    \"list\": [\n        2,\n        1\n    ]\n}"
+        );
+    }
 }
@@ -1,6 +1,9 @@
 use regex::Regex;
 use std::fmt::Display;
 use std::str::FromStr;
+use toml_edit::Value;
+
+use crate::TomlVal;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 /// Keys can contain either name segments or array indexes.
@@ -29,31 +32,73 @@ pub struct Keyspec {
 }
 
 impl Display for Keyspec {
+    /// Names that would be read back as something else, such as ones holding dots or
+    /// made only of digits, are double-quoted, so the key parses back to itself.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "{}",
             self.subkeys
                 .iter()
-                .map(|k| k.to_string())
+                .map(|k| match k {
+                    KeySegment::Name(n) if needs_quotes(n) => Value::from(n.as_str()).to_string(),
+                    _ => k.to_string(),
+                })
                 .collect::<Vec<String>>()
                 .join(".")
         )
     }
 }
 
+/// True if a name written as it is wouldn't parse back to the same key segment.
+fn needs_quotes(name: &str) -> bool {
+    name.contains(['.', '"']) || name.parse::<usize>().is_ok() || array_pattern().is_match(name)
+}
+
+/// The pattern for segments that look like "xxx[yyy]", which are array references.
+fn array_pattern() -> Regex {
+    // it's the cheesiest thing in the world to implement this with regex, but I am cheesy
+    Regex::new(r"(\w+)\[(\d+)\]").unwrap()
+}
+
+impl Keyspec {
+    /// Write this key the way it would appear on the left-hand side of a toml
+    /// assignment, as a dotted key. Keys that index into arrays can't be expressed
+    /// that way, so they have no toml form.
+    pub fn to_toml_key(&self) -> Option<String> {
+        let names: Option<Vec<String>> = self
+            .subkeys
+            .iter()
+            .map(|k| match k {
+                KeySegment::Name(n) => Some(toml_edit::Key::new(n).display_repr().to_string()),
+                KeySegment::Index(_) => None,
+            })
+            .collect();
+        names.map(|names| names.join("."))
+    }
+}
+
 impl FromStr for Keyspec {
     type Err = anyhow::Error;
 
+    /// Segments are separated by dots. A segment in double quotes is a name, taken
+    /// as it is, so `"a.b".c` has two segments and `a."1"` has no array index.
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let tokens: Vec<&str> = input.split('.').collect();
+        let tokens = split_segments(input);
         let mut subkeys: Vec<KeySegment> = Vec::with_capacity(tokens.len() * 2);
 
         // Tokens that look like "xxx[yyy]" are array references
-        // it's the cheesiest thing in the world to implement this with regex, but I am cheesy
-        let arraypatt = Regex::new(r"(\w+)\[(\d+)\]").unwrap();
+        let arraypatt = array_pattern();
 
         tokens.iter().try_for_each(|t| {
+            if t.starts_with('"') {
+                let name = match t.parse::<Value>() {
+                    Ok(Value::String(s)) => s.value().clone(),
+                    _ => anyhow::bail!("{} is not a valid quoted key segment", t),
+                };
+                subkeys.push(KeySegment::Name(name));
+                return Ok(());
+            }
             let maybe_captures = arraypatt.captures(t);
             match maybe_captures {
                 None => {
@@ -79,6 +124,119 @@ impl FromStr for Keyspec {
     }
 }
 
+/// Split a key at the dots that aren't inside double quotes.
+fn split_segments(input: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, c) in input.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' if quoted => quoted = false,
+            '"' if idx == start => quoted = true,
+            '.' if !quoted => {
+                tokens.push(&input[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    tokens.push(&input[start..]);
+    tokens
+}
+
+#[derive(Debug, Clone)]
+/// A key to look up, with an optional value to use if the key is missing.
+pub struct Lookup {
+    pub key: Keyspec,
+    pub default: Option<Value>,
+}
+
+impl FromStr for Lookup {
+    type Err = anyhow::Error;
+
+    /// Lookups are written `key` or `key=default`. Defaults are interpreted the same
+    /// way values passed to `set` are, so `port=8080` defaults to a number. An `=` inside
+    /// a quoted key segment is part of the key.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match split_default(input) {
+            None => Ok(Lookup {
+                key: Keyspec::from_str(input)?,
+                default: None,
+            }),
+            Some((key, default)) => Ok(Lookup {
+                key: Keyspec::from_str(key)?,
+                default: Some(TomlVal::from_str(default)?.inner),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A comma-separated list of lookups given on the command-line, for fetching
+/// several values with a single read of the file.
+pub struct KeyList {
+    pub lookups: Vec<Lookup>,
+}
+
+impl FromStr for KeyList {
+    type Err = anyhow::Error;
+
+    /// Commas inside quotes or brackets don't separate lookups, so a default such as
+    /// `name='a, b'` can hold them.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let lookups = split_lookups(input)?
+            .into_iter()
+            .map(Lookup::from_str)
+            .collect::<Result<Vec<Lookup>, _>>()?;
+        Ok(KeyList { lookups })
+    }
+}
+
+/// Split a lookup at the first `=` that isn't inside quotes.
+fn split_default(input: &str) -> Option<(&str, &str)> {
+    let mut quote: Option<char> = None;
+    for (idx, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '=') => return Some((&input[..idx], &input[idx + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split a list of lookups at the commas that aren't inside quotes or brackets.
+fn split_lookups(input: &str) -> Result<Vec<&str>, anyhow::Error> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quote: Option<char> = None;
+    let mut depth = 0usize;
+    for (idx, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => {
+                parts.push(&input[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    if let Some(q) = quote {
+        anyhow::bail!("{input} has an unclosed {q}");
+    }
+    parts.push(&input[start..]);
+    Ok(parts)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +290,92 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn key_lists() {
+        let list = KeyList::from_str("a.b").unwrap();
+        assert_eq!(list.lookups.len(), 1);
+        assert!(list.lookups[0].default.is_none());
+
+        let list = KeyList::from_str("a.b,c[1],server.port=8080,name='8080'").unwrap();
+        assert_eq!(list.lookups.len(), 4);
+        assert_eq!(list.lookups[1].key.subkeys[1], KeySegment::Index(1));
+        assert!(list.lookups[1].default.is_none());
+        assert_eq!(list.lookups[2].key.to_string(), "server.port");
+        assert!(matches!(
+            list.lookups[2].default,
+            Some(Value::Integer(ref i)) if *i.value() == 8080
+        ));
+        assert!(matches!(
+            list.lookups[3].default,
+            Some(Value::String(ref s)) if s.value() == "8080"
+        ));
+
+        let list = KeyList::from_str(r#"a='x, y',b=[1, 2],c={ d = "e,f" },g"#).unwrap();
+        assert_eq!(list.lookups.len(), 4);
+        assert!(matches!(
+            list.lookups[0].default,
+            Some(Value::String(ref s)) if s.value() == "x, y"
+        ));
+        assert!(matches!(
+            list.lookups[1].default,
+            Some(Value::String(ref s)) if s.value() == "[1, 2]"
+        ));
+        assert!(matches!(
+            list.lookups[2].default,
+            Some(Value::String(ref s)) if s.value() == r#"{ d = "e,f" }"#
+        ));
+        assert_eq!(list.lookups[3].key.to_string(), "g");
+        assert!(KeyList::from_str("a='x,b").is_err());
+
+        assert_eq!(
+            Keyspec::from_str("server.port").unwrap().to_toml_key(),
+            Some("server.port".to_string())
+        );
+        assert_eq!(Keyspec::from_str("a.1").unwrap().to_toml_key(), None);
+
+        let list = KeyList::from_str(r#""a=b".c,"x=y"=z=1,d"#).unwrap();
+        assert_eq!(list.lookups.len(), 3);
+        assert_eq!(
+            list.lookups[0].key.subkeys,
+            [
+                KeySegment::Name("a=b".to_string()),
+                KeySegment::Name("c".to_string())
+            ]
+        );
+        assert!(list.lookups[0].default.is_none());
+        assert_eq!(
+            list.lookups[1].key.subkeys,
+            [KeySegment::Name("x=y".to_string())]
+        );
+        assert!(matches!(
+            list.lookups[1].default,
+            Some(Value::String(ref s)) if s.value() == "z=1"
+        ));
+    }
+
+    #[test]
+    fn key_parsing_quoted() {
+        let key = Keyspec::from_str(r#"a."b.c"."1"."d[2]".e"#).unwrap();
+        assert_eq!(
+            key.subkeys,
+            [
+                KeySegment::Name("a".to_string()),
+                KeySegment::Name("b.c".to_string()),
+                KeySegment::Name("1".to_string()),
+                KeySegment::Name("d[2]".to_string()),
+                KeySegment::Name("e".to_string()),
+            ]
+        );
+        assert_eq!(key.to_string(), r#"a."b.c"."1"."d[2]".e"#);
+
+        let key = Keyspec::from_str(r#""say \"hi\"".x"#).unwrap();
+        assert_eq!(key.subkeys[0], KeySegment::Name(r#"say "hi""#.to_string()));
+        assert_eq!(
+            Keyspec::from_str(&key.to_string()).unwrap().subkeys,
+            key.subkeys
+        );
+        assert_eq!(Keyspec::from_str("a.1").unwrap().to_string(), "a.1");
+        assert!(Keyspec::from_str(r#""a.b"#).is_err());
+    }
 }
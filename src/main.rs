@@ -6,21 +6,37 @@ use std::io::BufReader;
 use std::str::FromStr;
 use toml_edit::{Document, Item, Value};
 
+mod batch;
+mod convert;
+mod diff;
+mod embedded;
+use embedded::Embedded;
 mod json;
-use json::format_json;
+mod merge;
+use json::{format_json, JsonOptions};
 mod bash;
-use bash::format_bash;
+use bash::BashOptions;
+mod bump;
+mod env;
+mod increment;
 mod keys;
+mod overrides;
+mod patch;
+mod shells;
+mod tabular;
 use keys::*;
+mod yaml;
+use yaml::format_yaml;
 
-#[derive(Parser, Debug)]
+#[derive(Clone, Parser, Debug)]
 #[clap(name = "🍅 tomato", version)]
 /// A command-line tool to get and set values in toml files while preserving comments and
 /// formatting.
 ///
 /// Keys are written using `.` to separate path segments. You can use `array[idx]` syntax to index
 /// into arrays if you want to. For example, to get the name of the current crate you're working on,
-/// you'd run `tomato get Cargo.toml package.name`.
+/// you'd run `tomato get Cargo.toml package.name`. Put a segment in double quotes if it holds a dot
+/// or is a number that isn't an index: `tomato get '"a.b".c' file.toml`.
 ///
 /// By default tomato emits data in a form suitable for immediate use in bash scripts if they are
 /// primitive values: strings are unquoted, for instance. If you want to use more complex data
@@ -29,29 +45,197 @@ use keys::*;
 /// To read from stdin instead of a file, omit the file argument. Operating on stdin changes
 /// the behavior of set and rm somewhat, under the assumption that you are using this tool in
 /// a shell script. If you read from stdin, normal output (the old value) is suppressed. Instead
-/// the modified file is written to stdout in json if you requested json, toml otherwise.
-/// The 'bash' format option is ignored.
+/// the modified file is written to stdout in json or yaml if you requested one of those, toml
+/// otherwise. The 'bash' format option is ignored. Use --output and --print to choose explicitly where
+/// the modified document goes and what gets printed, whatever you read from.
 pub struct Args {
-    /// How to format the output: json, toml, bash, or raw
+    /// How to format the output: json, toml, yaml, bash, zsh, fish, powershell, nu, env,
+    /// export, csv, tsv, or raw
     #[clap(short, long, global = true, default_value = "raw")]
     format: Format,
     /// Back up the file to <filepath>.bak if we write a new version. This option
     /// is ignored when we're operating on stdin.
     #[clap(long, short, global = true)]
     backup: bool,
+    /// When editing several files, keep going if one of them can't be edited. Errors
+    /// are reported to stderr and tomato exits with a non-zero status at the end.
+    #[clap(long, global = true)]
+    continue_on_error: bool,
+    /// Write the modified document to this path instead of back to the file it was read
+    /// from. Works with stdin as well as with a single input file.
+    #[clap(long, short, global = true)]
+    output: Option<String>,
+    /// Modify files in place. This is the default unless the environment variable
+    /// TOMATO_REQUIRE_IN_PLACE is set, in which case tomato refuses to modify a file
+    /// without either this flag or --output.
+    #[clap(long, short, global = true, conflicts_with = "output")]
+    in_place: bool,
+    /// What to print after making an edit: the whole modified document (doc), the previous
+    /// values (old), the new values (new), or nothing (none). Defaults to old if we wrote
+    /// a file and doc if we didn't.
+    #[clap(long, short, global = true)]
+    print: Option<Print>,
+    /// Work on the toml embedded in another kind of file: `+++` front matter in markdown,
+    /// or the ```` ```cargo ```` block in the `//!` doc comment of a cargo script. Only
+    /// the toml is changed when the file is written back. The `+++` must be the first line
+    /// of the file, and the `//!` must start each line, without indentation.
+    #[clap(long, short, global = true)]
+    embedded: bool,
+    /// Pretty-print json output, indented by two spaces.
+    #[clap(long, global = true, help_heading = "JSON OUTPUT")]
+    pretty: bool,
+    /// Pretty-print json output, indented by this many spaces.
+    #[clap(
+        long,
+        global = true,
+        value_name = "SPACES",
+        help_heading = "JSON OUTPUT"
+    )]
+    indent: Option<usize>,
+    /// Sort the keys of json objects. This is the default, except for `convert`, which
+    /// keeps documents in order unless asked to sort them.
+    #[clap(long, global = true, help_heading = "JSON OUTPUT")]
+    sort_keys: bool,
+    /// Keep the keys of json objects in the order they appear in the toml file instead
+    /// of sorting them.
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "sort-keys",
+        help_heading = "JSON OUTPUT"
+    )]
+    document_order: bool,
+    /// Write toml datetimes as `{"$datetime": "..."}` objects instead of strings, so they
+    /// can be converted back to toml datetimes without guessing.
+    #[clap(long, global = true, help_heading = "JSON OUTPUT")]
+    typed_datetimes: bool,
+    /// End every value with a NUL byte, for `xargs -0` and `readarray -d ''`. A single
+    /// array gives one value per element; getting several keys gives one value per key,
+    /// with arrays written whole as json.
+    #[clap(
+        short = '0',
+        long,
+        global = true,
+        conflicts_with = "separator",
+        help_heading = "RAW OUTPUT"
+    )]
+    null: bool,
+    /// Separate array elements and the values of several keys with this instead of
+    /// newlines and tabs.
+    #[clap(long, global = true, value_name = "SEP", help_heading = "RAW OUTPUT")]
+    separator: Option<String>,
+    /// The variable to assign to in shell output. Defaults to the last segment of the key,
+    /// so `get -f bash package.name` assigns to `name`. Ignored when getting several keys,
+    /// which are each named after their full path.
+    #[clap(
+        long,
+        global = true,
+        value_name = "NAME",
+        value_parser = bash::parse_var_name,
+        help_heading = "SHELL OUTPUT"
+    )]
+    var: Option<String>,
+    /// Flatten tables into one variable per value, named for the path to the value:
+    /// `SERVER_HTTP_PORT=8080`. Tables with anything nested in them are always flattened,
+    /// since bash can't nest arrays.
+    #[clap(long, global = true, help_heading = "SHELL OUTPUT")]
+    flatten: bool,
+    /// Start flattened variable names with this instead of the variable name. Pass an
+    /// empty string for no prefix.
+    #[clap(
+        long,
+        global = true,
+        value_parser = bash::parse_name_part,
+        help_heading = "SHELL OUTPUT"
+    )]
+    prefix: Option<String>,
+    /// Join the parts of flattened variable names with this.
+    #[clap(
+        long,
+        global = true,
+        default_value = "_",
+        value_name = "SEP",
+        value_parser = bash::parse_name_part,
+        help_heading = "SHELL OUTPUT"
+    )]
+    key_separator: String,
+    /// The case of flattened variable names: upper, lower, or preserve.
+    #[clap(
+        long,
+        global = true,
+        default_value = "upper",
+        help_heading = "SHELL OUTPUT"
+    )]
+    case: bash::Case,
+    /// Make fish variables global and exported (`set -gx`) instead of local.
+    #[clap(long, global = true, help_heading = "SHELL OUTPUT")]
+    exported: bool,
     #[clap(subcommand)]
     cmd: Command,
 }
 
+impl Args {
+    /// Gather up the output format and the options that shape it.
+    pub fn style(&self) -> OutputStyle {
+        let indent = match (self.indent, self.pretty) {
+            (Some(width), _) => Some(width),
+            (None, true) => Some(2),
+            (None, false) => None,
+        };
+        OutputStyle {
+            format: self.format,
+            json: JsonOptions {
+                indent,
+                document_order: self.document_order,
+                typed_datetimes: self.typed_datetimes,
+            },
+            raw: RawOptions {
+                separator: self.separator.clone(),
+                null: self.null,
+            },
+            bash: BashOptions {
+                var: self.var.clone(),
+                flatten: self.flatten,
+                prefix: self.prefix.clone(),
+                separator: self.key_separator.clone(),
+                case: self.case,
+                exported: self.exported,
+            },
+        }
+    }
+}
+
 #[derive(Clone, Debug, Subcommand)]
 pub enum Command {
     /// Get the value of a key from the given file
     #[clap(display_order = 1)]
     Get {
-        /// The key to look for. Use dots as path separators.
-        key: Keyspec,
-        /// The toml file to read from. Omit to read from stdin.
-        file: Option<String>,
+        /// The key to look for. Use dots as path separators. To fetch several keys at once,
+        /// separate them with commas. Add `=value` to a key to give it a default value to
+        /// use if it's missing, e.g. `server.host,server.port=8080`. Quote a default that
+        /// holds a comma, e.g. `name='a, b'`.
+        keys: KeyList,
+        /// The toml files to read from. Omit to read from stdin. Given several files, each
+        /// one overrides the ones before it, and tables are merged key by key, so the last
+        /// file to set a value wins.
+        files: Vec<String>,
+        /// A value to print if a key is missing. This is interpreted the same way
+        /// values passed to `set` are, so `8080` is a number and `'"8080"'` is a string.
+        /// Defaults given with `key=value` take precedence over this one.
+        #[clap(long)]
+        default: Option<TomlVal>,
+        /// Explain on stderr which file each value came from, and which files it shadowed.
+        #[clap(long)]
+        explain: bool,
+        /// Let environment variables starting with this prefix override the values in the
+        /// files. With a prefix of `APP`, `APP__DB__URL` overrides `db.url`. Values are
+        /// read as the same type as the value they override. The prefix can't be empty.
+        #[clap(long, value_name = "PREFIX")]
+        env_prefix: Option<String>,
+        /// Separate the prefix and the parts of the key in environment variable names
+        /// with this.
+        #[clap(long, default_value = "__", value_name = "SEP")]
+        env_separator: String,
     },
     /// Set a key to the given value, returning the previous value if one existed.
     #[clap(display_order = 2)]
@@ -59,21 +243,89 @@ pub enum Command {
         /// The key to set a value for. Use dots as path separators.
         key: Keyspec,
         /// The new value.
-        value: TomlVal,
-        /// The toml file to read from. Omit to read from stdin. If you read from stdin,
+        value: String,
+        /// Read the value as json instead, so `'{"a": [1, 2]}'` sets an inline table.
+        /// Datetimes written with --typed-datetimes come back as datetimes.
+        #[clap(long)]
+        json: bool,
+        /// Only set the key if it currently holds this value, read the same way as the new
+        /// value. Otherwise leave the file alone and exit with status 3. Given several files,
+        /// all of them are checked before any is written.
+        #[clap(long, value_name = "EXPECTED")]
+        if_equals: Option<String>,
+        /// Only set the key if it doesn't exist yet. Otherwise leave the file alone and exit
+        /// with status 3.
+        #[clap(long, conflicts_with = "if-equals")]
+        if_missing: bool,
+        /// The toml files to modify. Globs such as `crates/*/Cargo.toml` are expanded, and
+        /// the same edit is made to each file. Omit to read from stdin. If you read from stdin,
         /// the normal output of the old value is suppressed. Instead the modified file is written
         /// to stdout in json if you requested json, toml otherwise.
-        file: Option<String>,
+        files: Vec<String>,
     },
     /// Delete a key from the given file, returning the previous value if one existed
     #[clap(aliases = &["del", "delete", "delet", "forget", "regret", "remove", "unset", "yank", "yeet"], display_order=3)]
     Rm {
         /// The key to remove from the file. Use dots as path separators.
         key: Keyspec,
-        /// The toml file to read from. Omit to read from stdin. If you read from stdin,
+        /// The toml files to modify. Globs such as `crates/*/Cargo.toml` are expanded, and
+        /// the same edit is made to each file. Omit to read from stdin. If you read from stdin,
         /// the normal output of the old value is suppressed. Instead the modified file is written
         /// to stdout in json if you requested json, toml otherwise.
-        file: Option<String>,
+        files: Vec<String>,
+    },
+    /// Add to the number at a key, printing its new value. Integers and floats both work,
+    /// and the new value is written the same way as the old one: in hex if it was hex,
+    /// with underscores if it had them, and with as many decimal places.
+    #[clap(display_order = 2, allow_negative_numbers = true)]
+    Incr {
+        /// The key holding the number.
+        key: Keyspec,
+        /// The amount to add, instead of giving it before the files. Negative amounts subtract.
+        #[clap(long, value_name = "AMOUNT", allow_hyphen_values = true)]
+        by: Option<increment::Amount>,
+        /// Start the key at 0 if it doesn't exist, instead of stopping with an error.
+        #[clap(long)]
+        create: bool,
+        /// The amount to add, which defaults to 1, then the toml files to modify. The first
+        /// of these is the amount if it's a number and `--by` wasn't given. Globs are
+        /// expanded. Omit the files to read from stdin, in which case the modified file is
+        /// written to stdout.
+        #[clap(value_name = "BY|FILES")]
+        files: Vec<String>,
+    },
+    /// Subtract from the number at a key, printing its new value. Works exactly like incr.
+    #[clap(display_order = 2, allow_negative_numbers = true)]
+    Decr {
+        /// The key holding the number.
+        key: Keyspec,
+        /// The amount to subtract, instead of giving it before the files. Negative amounts add.
+        #[clap(long, value_name = "AMOUNT", allow_hyphen_values = true)]
+        by: Option<increment::Amount>,
+        /// Start the key at 0 if it doesn't exist, instead of stopping with an error.
+        #[clap(long)]
+        create: bool,
+        /// The amount to subtract, which defaults to 1, then the toml files to modify. The first
+        /// of these is the amount if it's a number and `--by` wasn't given. Globs are
+        /// expanded. Omit the files to read from stdin, in which case the modified file is
+        /// written to stdout.
+        #[clap(value_name = "BY|FILES")]
+        files: Vec<String>,
+    },
+    /// Bump the semantic version string at a key, printing the new version.
+    #[clap(display_order = 2)]
+    Bump {
+        /// The key holding the version, such as `package.version`.
+        key: Keyspec,
+        /// The part of the version to bump: major, minor, patch, or prerelease.
+        level: bump::Level,
+        /// Make the new version the first prerelease with this label, such as `1.3.0-beta.1`.
+        /// With prerelease, start counting with this label instead of the current one.
+        #[clap(long, value_name = "LABEL")]
+        pre: Option<String>,
+        /// The toml files to modify. Globs are expanded. Omit to read from stdin, in which
+        /// case the modified file is written to stdout.
+        files: Vec<String>,
     },
     /// Append the given value to an array, returning the previous array if one existed.
     #[clap(display_order = 1)]
@@ -82,18 +334,116 @@ pub enum Command {
         key: Keyspec,
         /// The new value.
         value: String,
-        /// The toml file to read from. Omit to read from stdin.
+        /// The toml files to modify. Globs such as `crates/*/Cargo.toml` are expanded, and
+        /// the same edit is made to each file. Omit to read from stdin. If you read from stdin,
+        /// the normal output of the old value is suppressed. Instead the modified file is written
+        /// to stdout in json if you requested json, toml otherwise.
+        files: Vec<String>,
+    },
+    /// Apply a list of edits to a file in a single read and write, returning the previous
+    /// value for each edit. If any edit fails, none of them are written.
+    #[clap(display_order = 4)]
+    Batch {
+        /// A file listing the edits to make, one per line: `set key value`, `rm key`, or
+        /// `append key value`. A json array of `{"op": "set", "key": "a.b", "value": 1}`
+        /// objects also works. Omit to read the edits from stdin.
+        #[clap(long)]
+        ops: Option<String>,
+        /// The toml files to modify. Globs are expanded, and every file gets the same edits.
+        /// Omit to read from stdin, in which case the edits must come from a file passed
+        /// with --ops. If you read from stdin, the modified file is written to stdout in json
+        /// if you requested json, toml otherwise.
+        files: Vec<String>,
+    },
+    /// Apply a json patch to a file: a list of add, remove, replace, move, copy, and test
+    /// operations addressed with tomato keys. If any operation fails, including a test
+    /// whose value doesn't match, nothing is written.
+    #[clap(display_order = 4)]
+    Patch {
+        /// The toml file to patch. Only one file can be named, since the patch file follows
+        /// it, but a quoted glob such as `'crates/*/Cargo.toml'` is expanded and every file
+        /// it matches gets the same patch.
+        file: String,
+        /// A json file holding the patch: an array of objects like `{"op": "replace",
+        /// "path": "db.pool", "value": 10}`. `tomato diff --patch` writes patches in this
+        /// form. Omit to read the patch from stdin.
+        patch: Option<String>,
+    },
+    /// Convert a json object to toml, reversing `-f json`. Given toml files, update them
+    /// to match the json instead, keeping the comments and formatting of everything that
+    /// didn't change.
+    #[clap(display_order = 4)]
+    FromJson {
+        /// The json to convert. Omit to read it from stdin.
+        #[clap(long)]
+        json: Option<String>,
+        /// Remove keys that are in the toml file but not in the json, so the file matches
+        /// the json exactly. By default they're left alone.
+        #[clap(long)]
+        prune: bool,
+        /// The toml files to update. Globs are expanded. Omit to print the converted
+        /// document instead, or write it to the path given with --output.
+        files: Vec<String>,
+    },
+    /// Deep-merge an overlay file into a base file, printing the result. Pass --in-place
+    /// to write it back to the base file instead, keeping the base file's comments and
+    /// formatting.
+    #[clap(display_order = 4)]
+    Merge {
+        /// How to merge arrays that are in both files: replace, append, or union.
+        #[clap(long, default_value = "replace")]
+        arrays: merge::ArrayStrategy,
+        /// What to do when the files have different values for a key: take the overlay's
+        /// value, keep the base's, or fail with an error. Arrays being replaced follow this too.
+        #[clap(long, default_value = "overlay")]
+        conflicts: merge::ConflictStrategy,
+        /// The file to merge into.
+        base: String,
+        /// The file to merge in.
+        overlay: String,
+    },
+    /// Compare two toml files by the data they hold, listing the keys that were added,
+    /// removed, or changed. Reordered keys and differences in formatting and comments are
+    /// ignored unless you ask for them. Exits with status 1 if the files differ, and 2 if
+    /// they couldn't be compared.
+    #[clap(display_order = 4)]
+    Diff {
+        /// Write the differences as a json list of add, remove, and replace operations
+        /// that turn the first file into the second.
+        #[clap(long)]
+        patch: bool,
+        /// Report keys whose values are the same but are formatted or commented differently.
+        #[clap(long)]
+        formatting: bool,
+        /// The file to compare from.
+        left: String,
+        /// The file to compare to.
+        right: String,
+    },
+    /// Convert a whole document among toml, json, and yaml. Converted toml gets a header
+    /// for each table, with small tables written inline.
+    #[clap(display_order = 4)]
+    Convert {
+        /// The format to read: toml, json, or yaml. Guessed from the file extension if
+        /// not given, or toml if there's no file.
+        #[clap(long)]
+        from: Option<convert::Syntax>,
+        /// The format to write: toml, json, or yaml.
+        #[clap(long)]
+        to: convert::Syntax,
+        /// The file to convert. Omit to read from stdin. The result is printed, or written
+        /// to the path given with --output.
         file: Option<String>,
     },
     /// Generate completions for the named shell.
-    #[clap(display_order = 4)]
+    #[clap(display_order = 5)]
     Completions {
         #[clap(arg_enum)]
         shell: Shell,
     },
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug)]
 /// How to format the output of more complex data structures.
 pub enum Format {
     /// Strings are not quoted; suitable for primitive data types; default
@@ -104,6 +454,24 @@ pub enum Format {
     Json,
     /// Output valid TOML
     Toml,
+    /// Output valid YAML
+    Yaml,
+    /// `KEY=value` lines for a dotenv file, with tables flattened
+    Env,
+    /// `export KEY=value` lines for a shell to source, with tables flattened
+    Export,
+    /// Suitable for eval in zsh
+    Zsh,
+    /// Suitable for eval in fish; tables are always flattened
+    Fish,
+    /// Suitable for Invoke-Expression in PowerShell
+    PowerShell,
+    /// Suitable for sourcing in nushell
+    Nushell,
+    /// Comma-separated values, with a header row, for arrays of tables
+    Csv,
+    /// Tab-separated values, with a header row, for arrays of tables
+    Tsv,
 }
 
 impl FromStr for Format {
@@ -115,11 +483,121 @@ impl FromStr for Format {
             "bash" => Ok(Format::Bash),
             "json" => Ok(Format::Json),
             "toml" => Ok(Format::Toml),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "env" | "dotenv" => Ok(Format::Env),
+            "export" => Ok(Format::Export),
+            "zsh" => Ok(Format::Zsh),
+            "fish" => Ok(Format::Fish),
+            "powershell" | "pwsh" => Ok(Format::PowerShell),
+            "nu" | "nushell" => Ok(Format::Nushell),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
             _ => Err(anyhow::anyhow!("{input} is not a supported output type")),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+/// What to print after editing a document.
+pub enum Print {
+    /// The whole modified document
+    Doc,
+    /// The values the edited keys held before the edit
+    Old,
+    /// The values the edited keys hold after the edit
+    New,
+    /// Nothing at all
+    None,
+}
+
+impl FromStr for Print {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "doc" => Ok(Print::Doc),
+            "old" => Ok(Print::Old),
+            "new" => Ok(Print::New),
+            "none" => Ok(Print::None),
+            _ => Err(anyhow::anyhow!("{input} is not something tomato can print")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// The output format plus any options that shape it.
+pub struct OutputStyle {
+    pub format: Format,
+    pub json: JsonOptions,
+    pub raw: RawOptions,
+    pub bash: BashOptions,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Options for raw output.
+pub struct RawOptions {
+    /// Separate array elements and the values of several keys with this.
+    pub separator: Option<String>,
+    /// Separate array elements, or the values of several keys, with NUL bytes, and end
+    /// the output with one.
+    pub null: bool,
+}
+
+impl RawOptions {
+    /// What goes between the elements of an array: a newline unless asked otherwise.
+    pub fn element_separator(&self) -> &str {
+        match (self.null, &self.separator) {
+            (true, _) => "\0",
+            (false, Some(sep)) => sep,
+            (false, None) => "\n",
+        }
+    }
+
+    /// What goes between the values of several keys: a tab unless asked otherwise.
+    pub fn field_separator(&self) -> &str {
+        match (self.null, &self.separator) {
+            (true, _) => "\0",
+            (false, Some(sep)) => sep,
+            (false, None) => "\t",
+        }
+    }
+}
+
+impl OutputStyle {
+    /// What to end output with: a NUL byte if NUL-delimited raw output was asked for, a
+    /// newline otherwise.
+    pub fn terminator(&self) -> &str {
+        match self.format {
+            Format::Raw if self.raw.null => "\0",
+            _ => "\n",
+        }
+    }
+
+    /// The shell to write variables for. Formats that aren't shells get bash.
+    pub fn shell(&self) -> Box<dyn bash::Dialect> {
+        match self.format {
+            Format::Zsh => Box::new(shells::Zsh),
+            Format::Fish => Box::new(shells::Fish {
+                exported: self.bash.exported,
+            }),
+            Format::PowerShell => Box::new(shells::PowerShell),
+            Format::Nushell => Box::new(shells::Nushell),
+            _ => Box::new(bash::Bash),
+        }
+    }
+}
+
+impl From<Format> for OutputStyle {
+    fn from(format: Format) -> Self {
+        OutputStyle {
+            format,
+            json: JsonOptions::default(),
+            raw: RawOptions::default(),
+            bash: BashOptions::default(),
+        }
+    }
+}
+
 // A wrapper around toml_edit values to allow us to distinguish between `"true"`
 // (a string) and `true` (a boolean) as command-line arguments.
 #[derive(Debug, Clone)]
@@ -142,13 +620,13 @@ impl FromStr for TomlVal {
             };
             core.into()
         } else if s == "true" {
-            Value::try_from(true).unwrap()
+            Value::from(true)
         } else if s == "false" {
-            Value::try_from(false).unwrap()
+            Value::from(false)
         } else if let Ok(v) = i64::from_str(s) {
-            Value::try_from(v).unwrap()
+            Value::from(v)
         } else if let Ok(v) = f64::from_str(s) {
-            Value::try_from(v).unwrap()
+            Value::from(v)
         } else {
             s.into()
         };
@@ -157,9 +635,8 @@ impl FromStr for TomlVal {
     }
 }
 
-/// Read the toml file and parse it. Respond with an error that gets propagated up
-/// if the file is not valid toml.
-pub fn parse_file(maybepath: Option<&String>) -> anyhow::Result<Document, anyhow::Error> {
+/// Read the named file into a string, or stdin if no file was named.
+pub fn read_input(maybepath: Option<&String>) -> anyhow::Result<String, anyhow::Error> {
     let mut data = String::new();
     if let Some(ref fpath) = maybepath {
         let file = File::open(fpath)?;
@@ -169,30 +646,350 @@ pub fn parse_file(maybepath: Option<&String>) -> anyhow::Result<Document, anyhow
         let mut reader = BufReader::new(std::io::stdin());
         reader.read_to_string(&mut data)?;
     }
-    let parsed = data
-        .parse::<Document>()
-        .unwrap_or_else(|_| panic!("{}", format!("The file {:?} is not valid toml.", maybepath)));
+    Ok(data)
+}
+
+/// Read the toml file and parse it. Respond with an error that gets propagated up
+/// if the file is not valid toml.
+pub fn parse_file(maybepath: Option<&String>) -> anyhow::Result<Document, anyhow::Error> {
+    let data = read_input(maybepath)?;
+    let parsed = data.parse::<Document>().map_err(|e| {
+        let name = maybepath.map(|p| p.as_str()).unwrap_or("stdin");
+        anyhow::anyhow!("The file {name} is not valid toml.\n{e}")
+    })?;
 
     Ok(parsed)
 }
 
-pub fn write_file(toml: &Document, fpath: &str, backup: bool) -> anyhow::Result<(), anyhow::Error> {
-    if backup {
+/// Read the input and parse it, finding the toml embedded in it if that was asked for.
+/// Respond with the document and, for embedded toml, the file it came from.
+pub fn read_document(
+    maybepath: Option<&String>,
+    embedded: bool,
+) -> anyhow::Result<(Document, Option<Embedded>), anyhow::Error> {
+    if !embedded {
+        return Ok((parse_file(maybepath)?, None));
+    }
+    parse_document(&read_input(maybepath)?, maybepath, embedded)
+}
+
+/// Parse text that has already been read from the named file, or stdin if no file was
+/// named, finding the toml embedded in it if that was asked for.
+pub fn parse_document(
+    text: &str,
+    maybepath: Option<&String>,
+    embedded: bool,
+) -> anyhow::Result<(Document, Option<Embedded>), anyhow::Error> {
+    let name = maybepath.map(|p| p.as_str()).unwrap_or("stdin");
+    if !embedded {
+        let parsed = text
+            .parse::<Document>()
+            .map_err(|e| anyhow::anyhow!("The file {name} is not valid toml.\n{e}"))?;
+        return Ok((parsed, None));
+    }
+    let (host, toml) = Embedded::split(text).map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+    let parsed = toml
+        .parse::<Document>()
+        .map_err(|e| anyhow::anyhow!("The toml embedded in {name} is not valid.\n{e}"))?;
+    Ok((parsed, Some(host)))
+}
+
+/// The full text to write for an edited document: the document itself, or the file it
+/// was embedded in with the new toml in place of the old.
+pub fn document_text(toml: &Document, host: Option<&Embedded>) -> String {
+    match host {
+        Some(host) => host.join(&toml.to_string()),
+        None => toml.to_string(),
+    }
+}
+
+/// Write the document, or any other text, to the given path. The new version is
+/// written next to the original and then renamed over it, so a failure partway
+/// through never leaves a truncated file behind. If `expected` is given, the file
+/// must still hold exactly that text just before it's replaced, so that changes made
+/// by someone else since we read it aren't lost.
+pub fn write_file(
+    contents: &impl std::fmt::Display,
+    fpath: &str,
+    backup: bool,
+    expected: Option<&str>,
+) -> anyhow::Result<(), anyhow::Error> {
+    if backup && std::path::Path::new(fpath).exists() {
         std::fs::copy(fpath, format!("{}.bak", fpath))?;
     }
-    let mut output = File::create(fpath)?;
-    // Note for future work: this won't be great for large files
-    write!(output, "{toml}")?;
+    // Follow symlinks so we replace the file they point to, not the link.
+    let target = std::fs::canonicalize(fpath).unwrap_or_else(|_| fpath.into());
+    let mut tmpname = target.clone().into_os_string();
+    tmpname.push(".tomato-tmp");
+    let tmppath = std::path::PathBuf::from(tmpname);
+
+    let written = (|| -> anyhow::Result<(), anyhow::Error> {
+        let mut output = File::create(&tmppath)?;
+        // Note for future work: this won't be great for large files
+        write!(output, "{contents}")?;
+        output.sync_all()?;
+        if let Ok(meta) = std::fs::metadata(&target) {
+            std::fs::set_permissions(&tmppath, meta.permissions())?;
+        }
+        if let Some(expected) = expected {
+            if std::fs::read_to_string(&target)? != expected {
+                anyhow::bail!("{fpath} changed while it was being edited, so it was left alone");
+            }
+        }
+        std::fs::rename(&tmppath, &target)?;
+        Ok(())
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmppath);
+    }
+    written
+}
+
+/// Expand any globs in the list of file arguments. Arguments that aren't globs
+/// are passed through untouched, so missing files get reported when we try to
+/// read them. A glob that matches nothing is an error.
+pub fn expand_files(files: &[String]) -> anyhow::Result<Vec<String>, anyhow::Error> {
+    let mut expanded = Vec::with_capacity(files.len());
+    for file in files {
+        if !file.contains(['*', '?', '[']) {
+            expanded.push(file.clone());
+            continue;
+        }
+        let before = expanded.len();
+        for entry in glob::glob(file)? {
+            expanded.push(entry?.to_string_lossy().to_string());
+        }
+        if expanded.len() == before {
+            anyhow::bail!("no files match {file}");
+        }
+    }
+    Ok(expanded)
+}
+
+/// True if the environment asks us to refuse in-place edits unless `--in-place`
+/// was passed explicitly.
+fn in_place_required() -> bool {
+    match std::env::var("TOMATO_REQUIRE_IN_PLACE") {
+        Ok(v) => !matches!(v.to_lowercase().as_str(), "" | "0" | "false" | "no"),
+        Err(_) => false,
+    }
+}
+
+/// Make the same edit to each of the given files, or to stdin if there are none.
+/// Each file is read, edited, and written on its own, either back in place or to
+/// the path given with `--output`. What gets printed is chosen with `--print`; by
+/// default that's the previous values if we wrote a file, and the whole modified
+/// document if we didn't. Output for several files is prefixed with file names.
+pub fn edit_files<F>(args: &Args, files: &[String], edit: F) -> anyhow::Result<(), anyhow::Error>
+where
+    F: Fn(&mut Document) -> anyhow::Result<Vec<Change>, anyhow::Error>,
+{
+    let paths = expand_files(files)?;
+    if paths.is_empty() {
+        if args.in_place {
+            anyhow::bail!("--in-place needs a file to edit, but we're reading from stdin.");
+        }
+        let (mut toml, host) = read_document(None, args.embedded)?;
+        let changes = edit(&mut toml)?;
+        if let Some(ref outpath) = args.output {
+            write_file(
+                &document_text(&toml, host.as_ref()),
+                outpath,
+                args.backup,
+                None,
+            )?;
+        }
+        let default_print = if args.output.is_some() {
+            Print::Old
+        } else {
+            Print::Doc
+        };
+        let print = args.print.unwrap_or(default_print);
+        let report = match (print, &host) {
+            // The whole document is the file the toml was embedded in.
+            (Print::Doc, Some(_)) => Some(document_text(&toml, host.as_ref())),
+            _ => format_report(&toml, &changes, print, &args.style()),
+        };
+        if let Some(report) = report {
+            print!("{report}{}", args.style().terminator());
+        }
+        return Ok(());
+    }
+
+    if args.output.is_some() && paths.len() > 1 {
+        anyhow::bail!("--output can only be used when editing a single file.");
+    }
+    if args.output.is_none() && !args.in_place && in_place_required() {
+        anyhow::bail!(
+            "TOMATO_REQUIRE_IN_PLACE is set; pass --in-place to modify files in place, or --output."
+        );
+    }
+
+    let print = args.print.unwrap_or(Print::Old);
+    let prefixed = paths.len() > 1;
+    let mut failures = 0;
+    for path in paths.iter() {
+        let result = read_input(Some(path)).and_then(|original| {
+            let (mut toml, host) = parse_document(&original, Some(path), args.embedded)?;
+            let changes = edit(&mut toml)?;
+            let text = document_text(&toml, host.as_ref());
+            match args.output {
+                Some(ref outpath) => write_file(&text, outpath, args.backup, None)?,
+                None => write_file(&text, path, args.backup, Some(&original))?,
+            }
+            Ok(match (print, &host) {
+                (Print::Doc, Some(_)) => Some(text),
+                _ => format_report(&toml, &changes, print, &args.style()),
+            })
+        });
+        match result {
+            Ok(None) => {}
+            Ok(Some(report)) => {
+                let style = args.style();
+                if prefixed && style.terminator() == "\0" {
+                    // Values can hold newlines, so NUL output is prefixed item by item.
+                    report
+                        .split('\0')
+                        .for_each(|item| print!("{path}: {item}\0"));
+                } else if prefixed {
+                    report.lines().for_each(|line| println!("{path}: {line}"));
+                } else {
+                    print!("{report}{}", style.terminator());
+                }
+            }
+            Err(e) if !prefixed => return Err(e),
+            Err(e) if args.continue_on_error => {
+                eprintln!("{path}: {e}");
+                failures += 1;
+            }
+            Err(e) => anyhow::bail!("{path}: {e}"),
+        }
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{failures} of {} files could not be edited", paths.len());
+    }
     Ok(())
 }
 
+/// One edit made to a document: the operation, the key it touched, and the values
+/// that key held before and after the edit.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub op: &'static str,
+    pub key: Keyspec,
+    pub old: Item,
+    pub new: Item,
+}
+
+impl Change {
+    /// Record an edit that has just been made to the document, looking up the new
+    /// value of the key.
+    pub fn new(
+        op: &'static str,
+        key: &Keyspec,
+        old: Item,
+        toml: &mut Document,
+    ) -> anyhow::Result<Self, anyhow::Error> {
+        let new = get_key(toml, key)?;
+        Ok(Change {
+            op,
+            key: key.clone(),
+            old,
+            new,
+        })
+    }
+}
+
+/// Produce the output requested with `--print`, if any, after editing a document.
+pub fn format_report(
+    toml: &Document,
+    changes: &[Change],
+    print: Print,
+    style: &OutputStyle,
+) -> Option<String> {
+    match print {
+        Print::None => None,
+        Print::Doc => Some(format_document(toml, style)),
+        Print::Old | Print::New => Some(format_changes(changes, print, style)),
+    }
+}
+
+/// Format a whole document: json, yaml, or env lines if that's what was asked for, toml
+/// otherwise.
+pub fn format_document(toml: &Document, style: &OutputStyle) -> String {
+    match style.format {
+        Format::Json | Format::Yaml | Format::Env | Format::Export => {
+            format_item_with(toml.as_item(), style)
+        }
+        _ => toml.to_string(),
+    }
+}
+
+/// Report either the previous or the new values from a set of edits. A single
+/// edit reports just the value. Several edits report one `op key: value` line
+/// each, an array of objects if json was requested, or one assignment per key for bash.
+pub fn format_changes(changes: &[Change], print: Print, style: &OutputStyle) -> String {
+    let value = |change: &Change| match print {
+        Print::New => change.new.clone(),
+        _ => change.old.clone(),
+    };
+    if let [change] = changes {
+        return format_keyed(&change.key, &value(change), style);
+    }
+    match style.format {
+        Format::Json => {
+            let field = match print {
+                Print::New => "value",
+                _ => "previous",
+            };
+            let report: Vec<serde_json::Value> = changes
+                .iter()
+                .map(|change| {
+                    serde_json::json!({
+                        "op": change.op,
+                        "key": change.key.to_string(),
+                        field: json::to_json(&value(change), &style.json),
+                    })
+                })
+                .collect();
+            json::json_to_string(serde_json::Value::Array(report), &style.json)
+        }
+        Format::Bash
+        | Format::Zsh
+        | Format::Fish
+        | Format::PowerShell
+        | Format::Nushell
+        | Format::Env
+        | Format::Export => {
+            let found: Vec<(Keyspec, Item)> = changes
+                .iter()
+                .map(|change| (change.key.clone(), value(change)))
+                .collect();
+            format_assignments(&found, style)
+        }
+        _ => changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "{} {}: {}",
+                    change.op,
+                    change.key,
+                    format_item_with(&value(change), style)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
 /// Given a key segment, find that key in this node. Returns None if the key segment is an
-/// int but the node is not an array.
+/// int but the node is not an array or an array of tables.
 pub fn get_in_node<'a>(key: &'a KeySegment, node: &'a mut Item) -> Option<&'a mut Item> {
     match key {
         KeySegment::Name(n) => node.get_mut(n),
         KeySegment::Index(idx) => {
-            if node.as_array().is_some() {
+            if node.is_array() || node.is_array_of_tables() {
                 node.get_mut(*idx)
             } else {
                 None
@@ -218,6 +1015,103 @@ pub fn get_key(toml: &mut Document, dotted_key: &Keyspec) -> Result<Item, anyhow
     Ok(node.clone())
 }
 
+/// Read each of the files, or stdin if there are none, responding with each document
+/// and the name of the file it came from.
+pub fn read_layers(
+    files: &[String],
+    embedded: bool,
+) -> anyhow::Result<Vec<(String, Document)>, anyhow::Error> {
+    let paths = expand_files(files)?;
+    if paths.is_empty() {
+        let (toml, _) = read_document(None, embedded)?;
+        return Ok(vec![("stdin".to_string(), toml)]);
+    }
+    paths
+        .into_iter()
+        .map(|path| {
+            let (toml, _) = read_document(Some(&path), embedded)?;
+            Ok((path, toml))
+        })
+        .collect()
+}
+
+/// Describe where the value of a key came from when reading several files. The last file
+/// to have the key supplied it and shadows the files before it, except for tables, which
+/// are merged from every file that has them. A value from an environment variable
+/// shadows every file, and any variables that overrode keys inside a table are listed.
+pub fn explain_key(
+    key: &Keyspec,
+    overridden: &[(Keyspec, String)],
+    layers: &mut [(String, Document)],
+) -> anyhow::Result<String, anyhow::Error> {
+    let mut found = Vec::new();
+    for (path, toml) in layers.iter_mut() {
+        let item = get_key(toml, key)?;
+        if !item.is_none() {
+            found.push((path.as_str(), item));
+        }
+    }
+    let exact = overridden
+        .iter()
+        .find(|(k, _)| k.subkeys == key.subkeys)
+        .map(|(_, var)| var);
+    if let Some(var) = exact {
+        let mut lines = vec![format!("{key}: the environment variable {var}")];
+        for (path, _) in found.iter().rev() {
+            lines.push(format!("  shadows {path}"));
+        }
+        return Ok(lines.join("\n"));
+    }
+    let (supplier, value) = match found.pop() {
+        Some(last) => last,
+        None => return Ok(format!("{key}: not found in any file")),
+    };
+    let mut lines = vec![format!("{key}: {supplier}")];
+    for (inner, var) in overridden
+        .iter()
+        .filter(|(k, _)| k.subkeys.len() > key.subkeys.len() && k.subkeys.starts_with(&key.subkeys))
+    {
+        lines.push(format!(
+            "  with {inner} from the environment variable {var}"
+        ));
+    }
+    for (path, item) in found.iter().rev() {
+        if value.is_table_like() && item.is_table_like() {
+            lines.push(format!("  merged with {path}"));
+        } else {
+            let shadowed = match item.as_value() {
+                Some(v) => v.to_string().trim().to_string(),
+                None => "a table".to_string(),
+            };
+            lines.push(format!("  shadows {path}: {shadowed}"));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Look up every key in the list, substituting defaults for missing keys where
+/// they were given. A key's own default wins over the fallback for the whole list.
+/// Responds with the keys paired with what was found for them.
+pub fn get_keys(
+    toml: &mut Document,
+    keys: &KeyList,
+    fallback: Option<&Value>,
+) -> Result<Vec<(Keyspec, Item)>, anyhow::Error> {
+    keys.lookups
+        .iter()
+        .map(|lookup| {
+            let item = match (
+                get_key(toml, &lookup.key)?,
+                lookup.default.as_ref().or(fallback),
+            ) {
+                (Item::None, Some(default)) => Item::Value(default.clone()),
+                (item, _) => item,
+            };
+            Ok((lookup.key.clone(), item))
+        })
+        .collect()
+}
+
 /// Remove the node corresponding to the given key. If the key was not found, we
 /// return an error saying so. Otherwise, we respond with the value that the key
 /// used to point to.
@@ -287,6 +1181,49 @@ pub fn set_key(
     Ok(original)
 }
 
+/// The exit status for a `set` that didn't happen because the key didn't hold what
+/// was expected.
+pub const PRECONDITION_FAILED: i32 = 3;
+
+/// The exit status for a `diff` that couldn't compare the files, as diff(1) has it.
+pub const DIFF_FAILED: i32 = 2;
+
+#[derive(Debug, Clone)]
+/// What a key must hold before `set` will change it.
+pub enum Precondition {
+    /// The key must hold this value.
+    Equals(Value),
+    /// The key must not exist.
+    Missing,
+}
+
+impl Precondition {
+    /// Check the current value of the key, responding with an error describing what
+    /// was found instead if it isn't what was expected.
+    pub fn check(&self, key: &Keyspec, current: &Item) -> Result<(), anyhow::Error> {
+        match self {
+            Precondition::Equals(expected) => {
+                let expected = Item::Value(expected.clone());
+                if current.is_none() {
+                    anyhow::bail!("key {key} is missing, not {}", format_toml(&expected));
+                }
+                if !merge::same(current, &expected) {
+                    anyhow::bail!(
+                        "key {key} is {}, not {}",
+                        format_toml(current),
+                        format_toml(&expected)
+                    );
+                }
+            }
+            Precondition::Missing if !current.is_none() => {
+                anyhow::bail!("key {key} already exists")
+            }
+            Precondition::Missing => {}
+        }
+        Ok(())
+    }
+}
+
 /// Append the given value to the array at the given key and respond with
 /// the original array value.
 /// Replaces null nodes if the parent was found, adding a new key to the
@@ -296,7 +1233,7 @@ pub fn set_key(
 pub fn append_value(
     toml: &mut Document,
     dotted_key: &Keyspec,
-    value: &str,
+    value: impl Into<Value>,
 ) -> Result<Item, anyhow::Error> {
     let mut node: &mut Item = toml.as_item_mut();
     let iterator = dotted_key.subkeys.iter();
@@ -322,11 +1259,124 @@ pub fn append_value(
 
 /// Format the given toml_edit item for the desired kind of output.
 pub fn format_item(item: &Item, output: Format) -> String {
-    match output {
-        Format::Raw => format_raw(item),
-        Format::Bash => format_bash(item),
-        Format::Json => format_json(item),
+    format_item_with(item, &output.into())
+}
+
+/// Format the given toml_edit item for the desired kind of output, with all the
+/// options given on the command line.
+pub fn format_item_with(item: &Item, style: &OutputStyle) -> String {
+    match style.format {
+        Format::Raw => format_raw(item, &style.raw),
+        Format::Bash | Format::Zsh | Format::Fish | Format::PowerShell | Format::Nushell => {
+            bash::format_shell(
+                &*style.shell(),
+                item,
+                style.bash.var.as_deref().unwrap_or("bashval"),
+                &style.bash,
+            )
+        }
+        Format::Json => json::format_json_with(item, &style.json),
         Format::Toml => format_toml(item),
+        Format::Yaml => format_yaml(item),
+        Format::Csv => tabular::format_delimited(item, b','),
+        Format::Tsv => tabular::format_delimited(item, b'\t'),
+        Format::Env | Format::Export => env::format_env(
+            item,
+            style.bash.var.as_deref().unwrap_or(""),
+            &style.bash,
+            matches!(style.format, Format::Export),
+        ),
+    }
+}
+
+/// Format the value found at a key. This is the same as `format_item_with`, except that
+/// bash and env output assign to a variable named after the key unless `--var` names one.
+pub fn format_keyed(key: &Keyspec, item: &Item, style: &OutputStyle) -> String {
+    let name = || {
+        style
+            .bash
+            .var
+            .clone()
+            .unwrap_or_else(|| bash::default_name(key))
+    };
+    match style.format {
+        Format::Bash | Format::Zsh | Format::Fish | Format::PowerShell | Format::Nushell => {
+            bash::format_shell(&*style.shell(), item, &name(), &style.bash)
+        }
+        Format::Env | Format::Export => env::format_env(
+            item,
+            &name(),
+            &style.bash,
+            matches!(style.format, Format::Export),
+        ),
+        _ => format_item_with(item, style),
+    }
+}
+
+/// Format the results of looking up several keys at once. Json and yaml get an object
+/// mapping keys to values, toml gets a table of dotted keys, bash and env get one variable
+/// assignment per key, and raw output is a single tab-separated line. Toml keys can't
+/// index into arrays, so asking for toml with keys that do is an error.
+pub fn format_lookups(
+    found: &[(Keyspec, Item)],
+    style: &OutputStyle,
+) -> anyhow::Result<String, anyhow::Error> {
+    let output = match style.format {
+        Format::Raw => found
+            .iter()
+            .map(|(_, item)| match item {
+                // With NUL output each key is a single item, so its elements can't be split.
+                Item::Value(Value::Array(_)) if style.raw.null => format_json(item),
+                _ => format_raw(item, &style.raw),
+            })
+            .collect::<Vec<String>>()
+            .join(style.raw.field_separator()),
+        Format::Bash
+        | Format::Zsh
+        | Format::Fish
+        | Format::PowerShell
+        | Format::Nushell
+        | Format::Env
+        | Format::Export => format_assignments(found, style),
+        Format::Csv => tabular::format_delimited_lookups(found, b','),
+        Format::Tsv => tabular::format_delimited_lookups(found, b'\t'),
+        Format::Json => {
+            let obj: serde_json::Map<String, serde_json::Value> = found
+                .iter()
+                .map(|(key, item)| (key.to_string(), json::to_json(item, &style.json)))
+                .collect();
+            json::json_to_string(serde_json::Value::Object(obj), &style.json)
+        }
+        Format::Toml => found
+            .iter()
+            .filter(|(_, item)| !item.is_none())
+            .map(|(key, item)| match key.to_toml_key() {
+                Some(name) => Ok(format!("{name} = {}", format_toml(item))),
+                None => Err(anyhow::anyhow!(
+                    "{key} indexes into an array, which a toml key can't express; get it on its own or pick another format"
+                )),
+            })
+            .collect::<Result<Vec<String>, _>>()?
+            .join("\n"),
+        Format::Yaml => {
+            let entries: Vec<(String, Item)> = found
+                .iter()
+                .map(|(key, item)| (key.to_string(), item.clone()))
+                .collect();
+            yaml::format_yaml_entries(&entries)
+        }
+    };
+    Ok(output)
+}
+
+/// Format several keys and their values as one variable assignment each, for the shell
+/// and env formats.
+pub fn format_assignments(found: &[(Keyspec, Item)], style: &OutputStyle) -> String {
+    match style.format {
+        Format::Env | Format::Export => {
+            env::format_env_assignments(found, &style.bash, matches!(style.format, Format::Export))
+        }
+        _ => bash::format_shell_assignments(&*style.shell(), found, &style.bash),
     }
 }
 
@@ -337,10 +1387,10 @@ pub fn format_toml(item: &Item) -> String {
 
 /// Format the item as a primitive type ready to use in bash. Falls back to
 /// json format for complex items, which might not be what you want.
-pub fn format_raw(item: &Item) -> String {
+pub fn format_raw(item: &Item, options: &RawOptions) -> String {
     match item {
         Item::None => "".to_string(),
-        Item::Value(v) => format_raw_value(v.clone()),
+        Item::Value(v) => format_raw_value(v.clone(), options),
         Item::Table(_) => format_json(item),
         Item::ArrayOfTables(_) => format_json(item),
     }
@@ -349,7 +1399,7 @@ pub fn format_raw(item: &Item) -> String {
 /// Format the value in a way useful immediately in bash scripts. This option
 /// falls back to json for anything that doesn't make sense in that context,
 /// such as toml tables.
-pub fn format_raw_value(v: Value) -> String {
+pub fn format_raw_value(v: Value, options: &RawOptions) -> String {
     match v {
         Value::String(s) => s.into_value(),
         Value::Integer(i) => i.into_value().to_string(),
@@ -361,10 +1411,10 @@ pub fn format_raw_value(v: Value) -> String {
         Value::Datetime(dt) => dt.into_value().to_string(),
         Value::Array(array) => array
             .iter()
-            .map(|xs| format_raw_value(xs.clone()))
+            .map(|xs| format_raw_value(xs.clone(), options))
             .collect::<Vec<String>>()
-            .join("\n"),
-        Value::InlineTable(_) => json::value_to_json(v).to_string(),
+            .join(options.element_separator()),
+        Value::InlineTable(_) => json::value_to_json(v, &JsonOptions::default()).to_string(),
     }
 }
 
@@ -372,65 +1422,272 @@ pub fn format_raw_value(v: Value) -> String {
 fn main() -> anyhow::Result<(), anyhow::Error> {
     let args = Args::parse();
 
-    match args.cmd {
-        Command::Get { key, file } => {
-            let mut toml = parse_file(file.as_ref())?;
-            let item = get_key(&mut toml, &key)?;
-            println!("{}", format_item(&item, args.format));
-        }
-        Command::Rm { key, file } => {
-            let mut toml = parse_file(file.as_ref())?;
-            let original = remove_key(&mut toml, &key)?;
-            match file {
-                None => {
-                    match args.format {
-                        Format::Json => println!("{}", format_item(toml.as_item(), args.format)),
-                        _ => println!("{toml}"),
-                    };
-                }
-                Some(filepath) => {
-                    write_file(&toml, &filepath, args.backup)?;
-                    println!("{}", format_item(&original, args.format));
+    match &args.cmd {
+        Command::Get {
+            keys,
+            files,
+            default,
+            explain,
+            env_prefix,
+            env_separator,
+        } => {
+            let mut layers = read_layers(files, args.embedded)?;
+            let mut toml = merge::layer_documents(layers.iter().map(|(_, doc)| doc))?;
+            let wanted: Vec<Keyspec> = keys.lookups.iter().map(|l| l.key.clone()).collect();
+            let overridden = match env_prefix {
+                Some(prefix) => overrides::apply_overrides(
+                    &mut toml,
+                    prefix,
+                    env_separator,
+                    std::env::vars(),
+                    Some(&wanted),
+                )?,
+                None => Vec::new(),
+            };
+            if *explain {
+                for lookup in keys.lookups.iter() {
+                    eprintln!("{}", explain_key(&lookup.key, &overridden, &mut layers)?);
                 }
             }
+            let fallback = default.as_ref().map(|d| d.inner.clone());
+            let found = get_keys(&mut toml, keys, fallback.as_ref())?;
+            let style = args.style();
+            let output = if let [(key, item)] = found.as_slice() {
+                format_keyed(key, item, &style)
+            } else {
+                format_lookups(&found, &style)?
+            };
+            print!("{output}{}", style.terminator());
+        }
+        Command::Rm { key, files } => {
+            edit_files(&args, files, |toml| {
+                let original = remove_key(toml, key)?;
+                Ok(vec![Change::new("rm", key, original, toml)?])
+            })?;
         }
-        Command::Set { key, value, file } => {
-            let mut toml = parse_file(file.as_ref())?;
-            let inner = value.inner;
-            let original = set_key(&mut toml, &key, &inner)?;
-            match file {
-                None => {
-                    match args.format {
-                        Format::Json => println!("{}", format_item(toml.as_item(), args.format)),
-                        _ => println!("{toml}"),
-                    };
+        Command::Set {
+            key,
+            value,
+            json,
+            if_equals,
+            if_missing,
+            files,
+        } => {
+            let parse = |text: &str| -> anyhow::Result<Value, anyhow::Error> {
+                if *json {
+                    json::json_to_value(&serde_json::from_str(text)?)
+                } else {
+                    Ok(TomlVal::from_str(text)?.inner)
+                }
+            };
+            let value = parse(value)?;
+            let precondition = match (if_equals, if_missing) {
+                (Some(expected), _) => Some(Precondition::Equals(parse(expected)?)),
+                (None, true) => Some(Precondition::Missing),
+                (None, false) => None,
+            };
+            // Check every file before writing any, so a failed check leaves them all alone.
+            // Each file is checked again as it's edited, in case it changed in the meantime.
+            if let (Some(precondition), false) = (&precondition, args.continue_on_error) {
+                for path in expand_files(files)? {
+                    let (mut toml, _) = read_document(Some(&path), args.embedded)?;
+                    if let Err(e) = precondition.check(key, &get_key(&mut toml, key)?) {
+                        eprintln!("Error: {path}: {e}");
+                        std::process::exit(PRECONDITION_FAILED);
+                    }
+                }
+            }
+            // Checks that fail get their own exit status, so scripts can tell a lost race
+            // from a broken file.
+            let unmet = std::cell::Cell::new(false);
+            let result = edit_files(&args, files, |toml| {
+                if let Some(ref precondition) = precondition {
+                    let current = get_key(toml, key)?;
+                    precondition
+                        .check(key, &current)
+                        .inspect_err(|_| unmet.set(true))?;
                 }
-                Some(filepath) => {
-                    write_file(&toml, &filepath, args.backup)?;
-                    println!("{}", format_item(&original, args.format));
+                let original = set_key(toml, key, &value)?;
+                Ok(vec![Change::new("set", key, original, toml)?])
+            });
+            if unmet.get() {
+                if let Err(e) = result {
+                    eprintln!("Error: {e}");
                 }
+                std::process::exit(PRECONDITION_FAILED);
             }
+            result?;
+        }
+        Command::Incr {
+            key,
+            by,
+            create,
+            files,
         }
-        Command::Append { key, value, file } => {
-            let mut toml = parse_file(file.as_ref())?;
-            let original = append_value(&mut toml, &key, &value)?;
-            match file {
-                None => {
-                    match args.format {
-                        Format::Json => println!("{}", format_item(toml.as_item(), args.format)),
-                        _ => println!("{toml}"),
-                    };
+        | Command::Decr {
+            key,
+            by,
+            create,
+            files,
+        } => {
+            let (amount, files) = increment::split_amount(*by, files);
+            let (op, amount) = match args.cmd {
+                Command::Decr { .. } => ("decr", amount.negated()),
+                _ => ("incr", amount),
+            };
+            // Print the new value unless asked for something else.
+            let args = Args {
+                print: args.print.or(if files.is_empty() {
+                    None
+                } else {
+                    Some(Print::New)
+                }),
+                ..args.clone()
+            };
+            edit_files(&args, files, |toml| {
+                let original = increment::increment(toml, key, amount, *create)?;
+                Ok(vec![Change::new(op, key, original, toml)?])
+            })?;
+        }
+        Command::Bump {
+            key,
+            level,
+            pre,
+            files,
+        } => {
+            let args = Args {
+                print: args.print.or(if files.is_empty() {
+                    None
+                } else {
+                    Some(Print::New)
+                }),
+                ..args.clone()
+            };
+            edit_files(&args, files, |toml| {
+                let original = bump::bump_key(toml, key, *level, pre.as_deref())?;
+                Ok(vec![Change::new("bump", key, original, toml)?])
+            })?;
+        }
+        Command::Append { key, value, files } => {
+            edit_files(&args, files, |toml| {
+                let original = append_value(toml, key, value)?;
+                Ok(vec![Change::new("append", key, original, toml)?])
+            })?;
+        }
+        Command::Batch { ops, files } => {
+            if ops.is_none() && files.is_empty() {
+                anyhow::bail!(
+                    "The edits and the toml file can't both come from stdin; pass --ops or a file."
+                );
+            }
+            let ops = batch::parse_ops(&read_input(ops.as_ref())?)?;
+            edit_files(&args, files, |toml| batch::apply_ops(toml, &ops))?;
+        }
+        Command::Patch { file, patch } => {
+            let ops = patch::parse_patch(&read_input(patch.as_ref())?)?;
+            edit_files(&args, std::slice::from_ref(file), |toml| {
+                patch::apply_patch(toml, &ops)
+            })?;
+        }
+        Command::FromJson { json, prune, files } => {
+            let parsed: serde_json::Value = serde_json::from_str(&read_input(json.as_ref())?)?;
+            let incoming = json::json_to_document(&parsed)?;
+            if files.is_empty() {
+                match args.output {
+                    Some(ref outpath) => write_file(&incoming, outpath, args.backup, None)?,
+                    None => print!("{incoming}"),
                 }
-                Some(filepath) => {
-                    write_file(&toml, &filepath, args.backup)?;
-                    println!("{}", format_item(&original, args.format));
+            } else {
+                edit_files(&args, files, |toml| {
+                    Ok(merge::update_document(toml, &incoming, *prune))
+                })?;
+            }
+        }
+        Command::Merge {
+            arrays,
+            conflicts,
+            base,
+            overlay,
+        } => {
+            let options = merge::MergeOptions {
+                arrays: *arrays,
+                conflicts: *conflicts,
+            };
+            let (overlay, _) = read_document(Some(overlay), args.embedded)?;
+            if args.in_place || args.output.is_some() {
+                edit_files(&args, std::slice::from_ref(base), |toml| {
+                    merge::merge_documents(toml, &overlay, &options)
+                })?;
+            } else {
+                let (mut toml, host) = read_document(Some(base), args.embedded)?;
+                merge::merge_documents(&mut toml, &overlay, &options)?;
+                let merged = match host {
+                    Some(ref host) => document_text(&toml, Some(host)),
+                    None => format_document(&toml, &args.style()),
+                };
+                if merged.ends_with('\n') {
+                    print!("{merged}");
+                } else {
+                    println!("{merged}");
+                }
+            }
+        }
+        Command::Diff {
+            patch,
+            formatting,
+            left,
+            right,
+        } => {
+            // Status 1 means the files differ, so trouble gets a status of its own.
+            let read = |path: &String| {
+                read_document(Some(path), args.embedded)
+                    .map(|(toml, _)| toml)
+                    .map_err(|e| anyhow::anyhow!("{path}: {e}"))
+            };
+            let compared = read(left).and_then(|left| {
+                let right = read(right)?;
+                Ok(diff::diff_documents(&left, &right, *formatting))
+            });
+            let diffs = match compared {
+                Ok(diffs) => diffs,
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    std::process::exit(DIFF_FAILED);
                 }
+            };
+            let style = args.style();
+            let report = match (patch, style.format) {
+                (true, _) => diff::format_patch(&diffs, &style.json),
+                (false, Format::Json) => diff::format_json(&diffs, &style.json),
+                _ => diff::format_text(&diffs),
+            };
+            if !report.is_empty() {
+                println!("{report}");
+            }
+            if !diffs.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Command::Convert { from, to, file } => {
+            let from = from
+                .or_else(|| file.as_deref().and_then(convert::Syntax::from_path))
+                .unwrap_or(convert::Syntax::Toml);
+            let doc = convert::parse_document(&read_input(file.as_ref())?, from)?;
+            // A converted document keeps its order unless sorting was asked for.
+            let options = JsonOptions {
+                document_order: !args.sort_keys,
+                ..args.style().json
+            };
+            let converted = convert::render_document(&doc, *to, &options);
+            match args.output {
+                Some(ref outpath) => write_file(&converted, outpath, args.backup, None)?,
+                None => print!("{converted}"),
             }
         }
         Command::Completions { shell } => {
             use clap::CommandFactory;
             let mut app = Args::command();
-            generate(shell, &mut app, "tomato", &mut std::io::stdout())
+            generate(*shell, &mut app, "tomato", &mut std::io::stdout())
         }
     };
 
@@ -479,6 +1736,39 @@ mod tests {
         assert!(doc.to_string().contains("bacon"));
     }
 
+    #[test]
+    fn preconditions() {
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let key = Keyspec::from_str("testcases.hashes.color").unwrap();
+        let current = get_key(&mut doc, &key).unwrap();
+
+        assert!(Precondition::Equals(Value::from("brown"))
+            .check(&key, &current)
+            .is_ok());
+        let err = Precondition::Equals(Value::from("taupe"))
+            .check(&key, &current)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"key testcases.hashes.color is "brown", not "taupe""#
+        );
+        assert!(Precondition::Missing.check(&key, &current).is_err());
+
+        let key = Keyspec::from_str("testcases.hashes.flavor").unwrap();
+        let current = get_key(&mut doc, &key).unwrap();
+        assert!(Precondition::Missing.check(&key, &current).is_ok());
+        let err = Precondition::Equals(Value::from(1))
+            .check(&key, &current)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "key testcases.hashes.flavor is missing, not 1"
+        );
+    }
+
     #[test]
     fn append() {
         let toml = include_str!("../fixtures/sample.toml");
@@ -526,7 +1816,7 @@ mod tests {
             .expect("test key should be valid");
         let item = append_value(&mut doc, &key, "banana")
             .expect("expected to be able to insert value 'banana'");
-        eprintln!("{}", doc.to_string());
+        eprintln!("{doc}");
         assert!(item.is_none());
         assert!(doc
             .to_string()
@@ -597,7 +1887,7 @@ mod tests {
             }
             _ => {
                 eprintln!("{:?}", tval.inner);
-                assert!(false, "should have been a string");
+                panic!("should have been a string");
             }
         }
 
@@ -609,7 +1899,7 @@ mod tests {
             }
             _ => {
                 eprintln!("{:?}", tval.inner);
-                assert!(false, "should have been a string");
+                panic!("should have been a string");
             }
         }
 
@@ -617,11 +1907,11 @@ mod tests {
         let tval2 = TomlVal::from_str(unquoted).expect("conversion should work");
         match tval2.inner {
             Value::Boolean(b) => {
-                assert_eq!(*b.value(), false);
+                assert!(!*b.value());
             }
             _ => {
                 eprintln!("{:?}", tval2.inner);
-                assert!(false, "should have been a boolean");
+                panic!("should have been a boolean");
             }
         }
     }
@@ -636,7 +1926,7 @@ mod tests {
             }
             _ => {
                 eprintln!("{:?}", tval.inner);
-                assert!(false, "should have been a string");
+                panic!("should have been a string");
             }
         }
 
@@ -648,7 +1938,7 @@ mod tests {
             }
             _ => {
                 eprintln!("{:?}", tval2.inner);
-                assert!(false, "should have been an integer");
+                panic!("should have been an integer");
             }
         }
 
@@ -660,7 +1950,7 @@ mod tests {
             }
             _ => {
                 eprintln!("{:?}", floatyval.inner);
-                assert!(false, "should have been an integer");
+                panic!("should have been an integer");
             }
         }
     }
@@ -692,9 +1982,317 @@ mod tests {
             .expect("the new value should be a valid toml value");
         match curval {
             Value::Boolean(b) => {
-                assert_eq!(*b.value(), false);
+                assert!(!*b.value());
             }
             _ => panic!("fetched value was supposed to be a boolean!"),
         }
     }
+
+    #[test]
+    fn get_several_keys() {
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+
+        let keys =
+            KeyList::from_str("package.name,testcases.fruits[1],package.missing,server.port=8080")
+                .expect("test keys should be valid");
+        let found = get_keys(&mut doc, &keys, None).expect("lookups should succeed");
+        assert_eq!(found.len(), 4);
+        assert!(found[2].1.is_none());
+
+        assert_eq!(
+            format_lookups(&found, &Format::Raw.into()).unwrap(),
+            "tomato\tplum\t\t8080"
+        );
+        assert_eq!(
+            format_lookups(&found, &Format::Json.into()).unwrap(),
+            r#"{"package.missing":null,"package.name":"tomato","server.port":8080,"testcases.fruits.1":"plum"}"#
+        );
+        let err = format_lookups(&found, &Format::Toml.into()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("testcases.fruits.1 indexes into an array"));
+        let keys = KeyList::from_str("package.name,package.missing,server.port=8080").unwrap();
+        let found = get_keys(&mut doc, &keys, None).expect("lookups should succeed");
+        assert_eq!(
+            format_lookups(&found, &Format::Toml.into()).unwrap(),
+            "package.name = \"tomato\"\nserver.port = 8080"
+        );
+    }
+
+    #[test]
+    fn get_with_default() {
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+
+        let keys = KeyList::from_str("server.port,server.host='localhost',package.name")
+            .expect("test keys should be valid");
+        let fallback = TomlVal::from_str("8080")
+            .expect("conversion should work")
+            .inner;
+        let found = get_keys(&mut doc, &keys, Some(&fallback)).expect("lookups should succeed");
+        assert_eq!(format_item(&found[0].1, Format::Json), "8080");
+        let (key, item) = &found[0];
+        assert_eq!(format_keyed(key, item, &Format::Bash.into()), "port=8080");
+        let mut style: OutputStyle = Format::Bash.into();
+        style.bash.var = Some("listen".to_string());
+        assert_eq!(format_keyed(key, item, &style), "listen=8080");
+        assert_eq!(format_item(&found[1].1, Format::Json), r#""localhost""#);
+        assert_eq!(format_item(&found[2].1, Format::Raw), "tomato");
+
+        let fallback = TomlVal::from_str(r#""8080""#)
+            .expect("conversion should work")
+            .inner;
+        let found = get_keys(&mut doc, &keys, Some(&fallback)).expect("lookups should succeed");
+        assert_eq!(format_item(&found[0].1, Format::Json), r#""8080""#);
+    }
+
+    #[test]
+    fn raw_separators() {
+        let toml = r#"
+lines = ["one\ntwo", "three"]
+name = "tomato"
+"#;
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let keys = KeyList::from_str("lines,name").expect("test keys should be valid");
+        let found = get_keys(&mut doc, &keys, None).expect("lookups should succeed");
+
+        let mut style: OutputStyle = Format::Raw.into();
+        assert_eq!(
+            format_lookups(&found, &style).unwrap(),
+            "one\ntwo\nthree\ttomato"
+        );
+        assert_eq!(style.terminator(), "\n");
+
+        style.raw.separator = Some(", ".to_string());
+        assert_eq!(
+            format_lookups(&found, &style).unwrap(),
+            "one\ntwo, three, tomato"
+        );
+
+        style.raw.null = true;
+        assert_eq!(
+            format_lookups(&found, &style).unwrap(),
+            "[\"one\\ntwo\",\"three\"]\0tomato"
+        );
+        assert_eq!(style.terminator(), "\0");
+        assert_eq!(format_item_with(&found[0].1, &style), "one\ntwo\0three");
+
+        let mut doc = "a = \"tomato\"\nb = \"sqlite://\"\n"
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let keys = KeyList::from_str("a,b").expect("test keys should be valid");
+        let found = get_keys(&mut doc, &keys, None).expect("lookups should succeed");
+        let output = format_lookups(&found, &style).unwrap() + style.terminator();
+        let items: Vec<&str> = output.split_terminator('\0').collect();
+        assert_eq!(items, ["tomato", "sqlite://"]);
+        style.format = Format::Json;
+        assert_eq!(style.terminator(), "\n");
+    }
+
+    #[test]
+    fn layered_lookups() {
+        let texts = [
+            ("defaults.toml", "[db]\nurl = \"sqlite://\"\npool = 5\n"),
+            ("site.toml", "[db]\nurl = \"postgres://site\"\n"),
+            ("local.toml", "[db]\nurl = \"postgres://localhost\"\n"),
+        ];
+        let mut layers: Vec<(String, Document)> = texts
+            .iter()
+            .map(|(path, text)| (path.to_string(), text.parse::<Document>().unwrap()))
+            .collect();
+        let mut toml = merge::layer_documents(layers.iter().map(|(_, doc)| doc))
+            .expect("layering should succeed");
+
+        let keys = KeyList::from_str("db.url,db.pool").unwrap();
+        let found = get_keys(&mut toml, &keys, None).expect("lookups should succeed");
+        assert_eq!(
+            format_lookups(&found, &Format::Raw.into()).unwrap(),
+            "postgres://localhost\t5"
+        );
+
+        let key = Keyspec::from_str("db.url").unwrap();
+        assert_eq!(
+            explain_key(&key, &[], &mut layers).unwrap(),
+            r#"db.url: local.toml
+  shadows site.toml: "postgres://site"
+  shadows defaults.toml: "sqlite://""#
+        );
+        let key = Keyspec::from_str("db").unwrap();
+        assert_eq!(
+            explain_key(&key, &[], &mut layers).unwrap(),
+            "db: local.toml\n  merged with site.toml\n  merged with defaults.toml"
+        );
+        assert_eq!(
+            explain_key(&key, &[(key.clone(), "APP__DB".to_string())], &mut layers).unwrap(),
+            "db: the environment variable APP__DB\n  shadows local.toml\n  shadows site.toml\n  shadows defaults.toml"
+        );
+        let pool = Keyspec::from_str("db.pool").unwrap();
+        assert_eq!(
+            explain_key(&key, &[(pool, "APP__DB__POOL".to_string())], &mut layers).unwrap(),
+            "db: local.toml\n  with db.pool from the environment variable APP__DB__POOL\n  merged with site.toml\n  merged with defaults.toml"
+        );
+        let key = Keyspec::from_str("db.user").unwrap();
+        assert_eq!(
+            explain_key(&key, &[], &mut layers).unwrap(),
+            "db.user: not found in any file"
+        );
+    }
+
+    #[test]
+    fn globs_expand() {
+        let files = vec![
+            "fixtures/*.toml".to_string(),
+            "fixtures/missing.toml".to_string(),
+        ];
+        let expanded = expand_files(&files).expect("the glob should match our fixture");
+        assert_eq!(
+            expanded,
+            vec!["fixtures/sample.toml", "fixtures/missing.toml"]
+        );
+
+        let files = vec!["fixtures/*.nope".to_string()];
+        assert!(expand_files(&files).is_err());
+    }
+
+    #[test]
+    fn writes_replace_the_file() {
+        let dir = std::env::temp_dir().join(format!("tomato-write-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("should be able to make a temp dir");
+        let fpath = dir.join("test.toml").to_string_lossy().to_string();
+        std::fs::write(&fpath, "old = true\n").expect("should be able to write the test file");
+
+        let mut doc = "# comment\nname = \"tomato\"\n"
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let key = Keyspec::from_str("name").unwrap();
+        set_key(&mut doc, &key, &Value::from("pluot")).expect("the key should be settable");
+        write_file(&doc, &fpath, true, Some("old = true\n")).expect("writing should succeed");
+
+        let written = std::fs::read_to_string(&fpath).expect("the file should exist");
+        assert_eq!(written, "# comment\nname = \"pluot\"\n");
+        let backup = std::fs::read_to_string(format!("{fpath}.bak")).expect("backup should exist");
+        assert_eq!(backup, "old = true\n");
+        assert!(!std::path::Path::new(&format!("{fpath}.tomato-tmp")).exists());
+
+        // Someone else changed the file after we read it.
+        let err = write_file(&"name = 1\n", &fpath, false, Some("old = true\n")).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("changed while it was being edited, so it was left alone"));
+        let written = std::fs::read_to_string(&fpath).expect("the file should exist");
+        assert_eq!(written, "# comment\nname = \"pluot\"\n");
+        assert!(!std::path::Path::new(&format!("{fpath}.tomato-tmp")).exists());
+        std::fs::remove_dir_all(&dir).expect("should be able to clean up");
+    }
+
+    #[test]
+    fn reports() {
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+
+        let key = Keyspec::from_str("testcases.hashes.color").unwrap();
+        let original = set_key(&mut doc, &key, &Value::from("taupe")).unwrap();
+        let color = Change::new("set", &key, original, &mut doc).unwrap();
+        let key = Keyspec::from_str("testcases.are_passing").unwrap();
+        let original = remove_key(&mut doc, &key).unwrap();
+        let passing = Change::new("rm", &key, original, &mut doc).unwrap();
+
+        let changes = vec![color.clone()];
+        let report = format_report(&doc, &changes, Print::Old, &Format::Raw.into());
+        assert_eq!(report.as_deref(), Some("brown"));
+        let report = format_report(&doc, &changes, Print::New, &Format::Json.into());
+        assert_eq!(report.as_deref(), Some(r#""taupe""#));
+        assert!(format_report(&doc, &changes, Print::None, &Format::Raw.into()).is_none());
+        let report = format_report(&doc, &changes, Print::Doc, &Format::Toml.into())
+            .expect("doc should produce output");
+        assert!(report.contains("color = \"taupe\""));
+
+        let changes = vec![color, passing];
+        let report = format_changes(&changes, Print::Old, &Format::Raw.into());
+        assert_eq!(
+            report,
+            "set testcases.hashes.color: brown\nrm testcases.are_passing: 1"
+        );
+        let report = format_changes(&changes, Print::New, &Format::Json.into());
+        assert_eq!(
+            report,
+            r#"[{"key":"testcases.hashes.color","op":"set","value":"taupe"},{"key":"testcases.are_passing","op":"rm","value":null}]"#
+        );
+    }
+
+    #[test]
+    fn counting_arguments() {
+        let parse = |argv: &[&str]| -> Result<(increment::Amount, Vec<String>), clap::Error> {
+            match Args::try_parse_from(argv)?.cmd {
+                Command::Incr { by, files, .. } | Command::Decr { by, files, .. } => {
+                    let (amount, files) = increment::split_amount(by, &files);
+                    Ok((amount, files.to_vec()))
+                }
+                _ => panic!("only counting commands are parsed here"),
+            }
+        };
+        assert_eq!(
+            parse(&["tomato", "incr", "build", "5", "Cargo.toml"]).unwrap(),
+            (
+                increment::Amount::Integer(5),
+                vec!["Cargo.toml".to_string()]
+            )
+        );
+        assert_eq!(
+            parse(&["tomato", "decr", "n", "-2.5", "a.toml", "b.toml"]).unwrap(),
+            (
+                increment::Amount::Float(-2.5, 1),
+                vec!["a.toml".to_string(), "b.toml".to_string()]
+            )
+        );
+        assert_eq!(
+            parse(&["tomato", "incr", "n", "a.toml"]).unwrap(),
+            (increment::Amount::Integer(1), vec!["a.toml".to_string()])
+        );
+        assert_eq!(
+            parse(&["tomato", "incr", "n", "3"]).unwrap(),
+            (increment::Amount::Integer(3), vec![])
+        );
+        assert_eq!(
+            parse(&["tomato", "incr", "--by", "-4", "n", "a.toml"]).unwrap(),
+            (increment::Amount::Integer(-4), vec!["a.toml".to_string()])
+        );
+        assert!(parse(&["tomato", "incr", "--by", "lots", "n", "a.toml"]).is_err());
+    }
+
+    #[test]
+    fn from_json_keeps_other_keys() {
+        let incoming =
+            json::json_to_document(&serde_json::json!({"package": {"version": "0.6.0"}}))
+                .expect("test json should convert");
+        let update = |argv: &[&str]| {
+            let prune = match Args::try_parse_from(argv).map(|args| args.cmd) {
+                Ok(Command::FromJson { prune, .. }) => prune,
+                other => panic!("from-json should parse, not {other:?}"),
+            };
+            let mut doc = "[package]\nversion = \"0.5.0\"\n\n[dependencies]\nanyhow = \"1.0\"\n"
+                .parse::<Document>()
+                .expect("test doc should be valid toml");
+            merge::update_document(&mut doc, &incoming, prune);
+            doc.to_string()
+        };
+
+        assert_eq!(
+            update(&["tomato", "from-json", "Cargo.toml"]),
+            "[package]\nversion = \"0.6.0\"\n\n[dependencies]\nanyhow = \"1.0\"\n"
+        );
+        assert_eq!(
+            update(&["tomato", "from-json", "--prune", "Cargo.toml"]),
+            "[package]\nversion = \"0.6.0\"\n"
+        );
+    }
 }
@@ -0,0 +1,535 @@
+/// Fold one document into another, touching only the keys whose values differ so
+/// that comments and formatting survive everywhere else.
+use std::str::FromStr;
+use toml_edit::{ArrayOfTables, Document, Item, Table, TableLike, Value};
+
+use crate::json::{to_json, JsonOptions};
+use crate::{Change, KeySegment, Keyspec};
+
+/// Update the document so it holds everything in `incoming`, responding with a record
+/// of each key that changed. If `prune` is set, keys that aren't in `incoming` are
+/// removed, so the result matches it exactly; otherwise they're left alone.
+pub fn update_document(toml: &mut Document, incoming: &Document, prune: bool) -> Vec<Change> {
+    let mut changes = Vec::new();
+    update_table(
+        toml.as_table_mut(),
+        incoming.as_table(),
+        prune,
+        &mut Vec::new(),
+        &mut changes,
+    );
+    changes
+}
+
+#[derive(Clone, Copy, Debug)]
+/// How to merge an array that's in both documents.
+pub enum ArrayStrategy {
+    /// Use the overlay's array
+    Replace,
+    /// Add the overlay's elements after the base's
+    Append,
+    /// Add the overlay's elements that the base doesn't already have
+    Union,
+}
+
+impl FromStr for ArrayStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "replace" => Ok(ArrayStrategy::Replace),
+            "append" => Ok(ArrayStrategy::Append),
+            "union" => Ok(ArrayStrategy::Union),
+            _ => Err(anyhow::anyhow!(
+                "{input} is not a way tomato can merge arrays"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// What to do when the two documents hold different values for the same key.
+pub enum ConflictStrategy {
+    /// Take the overlay's value
+    Overlay,
+    /// Keep the base's value
+    Base,
+    /// Refuse to merge
+    Error,
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "overlay" => Ok(ConflictStrategy::Overlay),
+            "base" => Ok(ConflictStrategy::Base),
+            "error" => Ok(ConflictStrategy::Error),
+            _ => Err(anyhow::anyhow!(
+                "{input} is not a way tomato can resolve conflicts"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// How to merge one document into another.
+pub struct MergeOptions {
+    pub arrays: ArrayStrategy,
+    pub conflicts: ConflictStrategy,
+}
+
+/// Deep-merge the overlay into the base document, responding with a record of each key
+/// that changed. Tables are merged key by key, arrays as the options ask, and anything
+/// else the two disagree about is a conflict. Keys only in the overlay are added at the
+/// end of the table they belong in.
+pub fn merge_documents(
+    base: &mut Document,
+    overlay: &Document,
+    options: &MergeOptions,
+) -> Result<Vec<Change>, anyhow::Error> {
+    let mut changes = Vec::new();
+    merge_table(
+        base.as_table_mut(),
+        overlay.as_table(),
+        options,
+        &mut Vec::new(),
+        &mut changes,
+    )?;
+    Ok(changes)
+}
+
+/// Layer documents over each other, as configuration files are layered: each one
+/// overrides the ones before it, tables are merged, and arrays are replaced.
+pub fn layer_documents<'a>(
+    mut documents: impl Iterator<Item = &'a Document>,
+) -> Result<Document, anyhow::Error> {
+    let mut layered = documents.next().cloned().unwrap_or_default();
+    let options = MergeOptions {
+        arrays: ArrayStrategy::Replace,
+        conflicts: ConflictStrategy::Overlay,
+    };
+    for overlay in documents {
+        merge_documents(&mut layered, overlay, &options)?;
+    }
+    Ok(layered)
+}
+
+/// Merge each key of the overlay table into the base table.
+fn merge_table(
+    base: &mut dyn TableLike,
+    overlay: &dyn TableLike,
+    options: &MergeOptions,
+    path: &mut Vec<KeySegment>,
+    changes: &mut Vec<Change>,
+) -> Result<(), anyhow::Error> {
+    for (k, new) in overlay.iter() {
+        path.push(KeySegment::Name(k.to_string()));
+        match base.get_mut(k) {
+            Some(old) => merge_item(old, new, options, path, changes)?,
+            None => {
+                base.insert(k, detached(new));
+                record(changes, "set", path, Item::None, new.clone());
+            }
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Merge one item of the overlay into the matching item of the base.
+fn merge_item(
+    old: &mut Item,
+    new: &Item,
+    options: &MergeOptions,
+    path: &mut Vec<KeySegment>,
+    changes: &mut Vec<Change>,
+) -> Result<(), anyhow::Error> {
+    if same(old, new) {
+        return Ok(());
+    }
+    if let (Some(old_table), Some(new_table)) = (old.as_table_like_mut(), new.as_table_like()) {
+        return merge_table(old_table, new_table, options, path, changes);
+    }
+
+    let previous = old.clone();
+    // Arrays that are replaced rather than combined are settled like any other conflict.
+    match (&mut *old, new, options.arrays) {
+        (
+            Item::Value(Value::Array(old_array)),
+            Item::Value(Value::Array(new_array)),
+            strategy @ (ArrayStrategy::Append | ArrayStrategy::Union),
+        ) => {
+            for v in new_array.iter() {
+                let present = || old_array.iter().any(|o| same_value(o, v));
+                if matches!(strategy, ArrayStrategy::Append) || !present() {
+                    old_array.push(v.clone());
+                }
+            }
+        }
+        (
+            Item::ArrayOfTables(old_tables),
+            Item::ArrayOfTables(new_tables),
+            strategy @ (ArrayStrategy::Append | ArrayStrategy::Union),
+        ) => {
+            for table in new_tables.iter() {
+                let present = || {
+                    old_tables
+                        .iter()
+                        .any(|o| same(&Item::Table(o.clone()), &Item::Table(table.clone())))
+                };
+                if matches!(strategy, ArrayStrategy::Append) || !present() {
+                    old_tables.push(detached_table(table));
+                }
+            }
+        }
+        _ => match options.conflicts {
+            ConflictStrategy::Overlay => replace(old, new),
+            ConflictStrategy::Base => return Ok(()),
+            ConflictStrategy::Error => {
+                let key = Keyspec {
+                    subkeys: path.to_vec(),
+                };
+                anyhow::bail!("the two files have different values for {key}");
+            }
+        },
+    }
+    record(changes, "set", path, previous, old.clone());
+    Ok(())
+}
+
+/// Replace one item with another. Inline values stay inline, and keep the whitespace
+/// and comments around them; tables are copied over whole.
+fn replace(old: &mut Item, new: &Item) {
+    if let Item::Value(old_value) = old {
+        if let Ok(mut value) = new.clone().into_value() {
+            *value.decor_mut() = old_value.decor().clone();
+            *old_value = value;
+            return;
+        }
+    }
+    *old = detached(new);
+}
+
+/// A copy of an item from another document. Its tables lose the places they had in that
+/// document, so they're written next to their parents in this one.
+fn detached(item: &Item) -> Item {
+    match item {
+        Item::Table(table) => Item::Table(detached_table(table)),
+        Item::ArrayOfTables(aot) => {
+            let mut copy = ArrayOfTables::new();
+            aot.iter()
+                .for_each(|table| copy.push(detached_table(table)));
+            Item::ArrayOfTables(copy)
+        }
+        _ => item.clone(),
+    }
+}
+
+/// A copy of a table from another document, without its place in that document.
+fn detached_table(table: &Table) -> Table {
+    let mut copy = Table::new();
+    copy.set_implicit(table.is_implicit());
+    copy.set_dotted(table.is_dotted());
+    // Bring along comments above the table, but otherwise space it the way new tables are.
+    let commented = table
+        .decor()
+        .prefix()
+        .and_then(|prefix| prefix.as_str())
+        .is_some_and(|prefix| prefix.contains('#'));
+    if commented {
+        *copy.decor_mut() = table.decor().clone();
+    }
+    for (k, v) in table.iter() {
+        copy.insert(k, detached(v));
+    }
+    copy
+}
+
+/// Two values are the same if they hold the same data, however they're formatted.
+fn same_value(left: &Value, right: &Value) -> bool {
+    same(&Item::Value(left.clone()), &Item::Value(right.clone()))
+}
+
+/// Two items are the same if they hold the same data, however they're formatted.
+pub fn same(left: &Item, right: &Item) -> bool {
+    let options = JsonOptions {
+        typed_datetimes: true,
+        ..Default::default()
+    };
+    to_json(left, &options) == to_json(right, &options)
+}
+
+/// Note that the key at this path changed.
+fn record(changes: &mut Vec<Change>, op: &'static str, path: &[KeySegment], old: Item, new: Item) {
+    changes.push(Change {
+        op,
+        key: Keyspec {
+            subkeys: path.to_vec(),
+        },
+        old,
+        new,
+    });
+}
+
+/// Update each key of the existing table to match the incoming one.
+fn update_table(
+    existing: &mut dyn TableLike,
+    incoming: &Table,
+    prune: bool,
+    path: &mut Vec<KeySegment>,
+    changes: &mut Vec<Change>,
+) {
+    if prune {
+        let missing: Vec<String> = existing
+            .iter()
+            .map(|(k, _)| k.to_string())
+            .filter(|k| !incoming.contains_key(k))
+            .collect();
+        for k in missing {
+            path.push(KeySegment::Name(k.clone()));
+            if let Some(old) = existing.remove(&k) {
+                record(changes, "rm", path, old, Item::None);
+            }
+            path.pop();
+        }
+    }
+
+    for (k, new) in incoming.iter() {
+        path.push(KeySegment::Name(k.to_string()));
+        match existing.get_mut(k) {
+            Some(old) => update_item(old, new, prune, path, changes),
+            None => {
+                existing.insert(k, new.clone());
+                record(changes, "set", path, Item::None, new.clone());
+            }
+        }
+        path.pop();
+    }
+}
+
+/// Update one item to match the incoming one. Tables and same-length arrays of tables
+/// are updated key by key; anything else is replaced outright if it differs.
+fn update_item(
+    old: &mut Item,
+    new: &Item,
+    prune: bool,
+    path: &mut Vec<KeySegment>,
+    changes: &mut Vec<Change>,
+) {
+    if same(old, new) {
+        return;
+    }
+    match (&mut *old, new) {
+        (Item::Table(old_table), Item::Table(new_table)) => {
+            update_table(old_table, new_table, prune, path, changes)
+        }
+        (Item::Value(toml_edit::Value::InlineTable(old_table)), Item::Table(new_table)) => {
+            update_table(old_table, new_table, prune, path, changes)
+        }
+        (Item::ArrayOfTables(old_tables), Item::ArrayOfTables(new_tables))
+            if old_tables.len() == new_tables.len() =>
+        {
+            for (idx, (old_table, new_table)) in
+                old_tables.iter_mut().zip(new_tables.iter()).enumerate()
+            {
+                path.push(KeySegment::Index(idx));
+                update_table(old_table, new_table, prune, path, changes);
+                path.pop();
+            }
+        }
+        (old, _) => {
+            let previous = old.clone();
+            replace(old, new);
+            record(changes, "set", path, previous, new.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::json_to_document;
+
+    #[test]
+    fn updates_keep_comments() {
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let options = JsonOptions {
+            typed_datetimes: true,
+            ..Default::default()
+        };
+        let mut json = to_json(doc.as_item(), &options);
+
+        // An unchanged round trip changes nothing at all.
+        let incoming = json_to_document(&json).expect("json from a document should convert");
+        assert!(update_document(&mut doc, &incoming, true).is_empty());
+        assert_eq!(doc.to_string(), toml);
+
+        json["testcases"]["hashes"]["color"] = "taupe".into();
+        json["testcases"]["inline_table"]["fruit"] = "fig".into();
+        json["nested"][1]["entry"] = "three".into();
+        json["testcases"]
+            .as_object_mut()
+            .unwrap()
+            .remove("are_complete");
+        json["added"] = serde_json::json!({ "flag": true });
+        let incoming = json_to_document(&json).expect("edited json should convert");
+
+        let changes = update_document(&mut doc, &incoming, false);
+        let keys: Vec<String> = changes.iter().map(|c| c.key.to_string()).collect();
+        assert_eq!(
+            keys,
+            [
+                "testcases.hashes.color",
+                "testcases.inline_table.fruit",
+                "nested.1.entry",
+                "added"
+            ]
+        );
+        let updated = doc.to_string();
+        assert!(updated.contains("# food not algorithms\n[testcases.hashes]\ncolor = \"taupe\""));
+        assert!(updated.contains(r#"fruit = "fig", "safe_pet""#));
+        assert!(updated.contains("are_complete = false"));
+        assert!(updated.contains("[added]\nflag = true"));
+
+        let changes = update_document(&mut doc, &incoming, true);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].op, "rm");
+        assert!(!doc.to_string().contains("are_complete"));
+    }
+
+    #[test]
+    fn merges_keep_comments() {
+        let base = r#"# the base
+[package]
+name = "tomato" # the name
+keywords = ["cli", "toml"]
+
+[dependencies]
+anyhow = "1.0"
+
+[[bin]]
+name = "tomato"
+"#;
+        let overlay = r#"
+[package]
+name = "broccoli"
+keywords = ["toml", "yaml"]
+edition = "2021"
+
+[package.metadata]
+docs = true
+
+[[bin]]
+name = "tomato"
+
+[[bin]]
+name = "tomatillo"
+
+[features]
+default = []
+"#;
+        let overlay = overlay
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let merged = |arrays, conflicts| {
+            let mut doc = base
+                .parse::<Document>()
+                .expect("test doc should be valid toml");
+            let options = MergeOptions { arrays, conflicts };
+            merge_documents(&mut doc, &overlay, &options).map(|_| doc.to_string())
+        };
+
+        let replaced = merged(ArrayStrategy::Replace, ConflictStrategy::Overlay).unwrap();
+        assert_eq!(
+            replaced,
+            r#"# the base
+[package]
+name = "broccoli" # the name
+keywords = ["toml", "yaml"]
+edition = "2021"
+
+[package.metadata]
+docs = true
+
+[dependencies]
+anyhow = "1.0"
+
+[[bin]]
+name = "tomato"
+
+[[bin]]
+name = "tomatillo"
+
+[features]
+default = []
+"#
+        );
+
+        let unioned = merged(ArrayStrategy::Union, ConflictStrategy::Base).unwrap();
+        assert!(unioned.contains("name = \"tomato\" # the name"));
+        assert!(unioned.contains(r#"keywords = ["cli", "toml", "yaml"]"#));
+        assert_eq!(unioned.matches("[[bin]]").count(), 2);
+
+        let appended = merged(ArrayStrategy::Append, ConflictStrategy::Base).unwrap();
+        assert!(appended.contains(r#"keywords = ["cli", "toml", "toml", "yaml"]"#));
+        assert_eq!(appended.matches("[[bin]]").count(), 3);
+
+        let err = merged(ArrayStrategy::Union, ConflictStrategy::Error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the two files have different values for package.name"
+        );
+    }
+
+    #[test]
+    fn replaced_arrays_follow_conflicts() {
+        let base = "ports = [80]\n\n[[bin]]\nname = \"tomato\"\n";
+        let overlay = "ports = [443]\n\n[[bin]]\nname = \"tomatillo\"\n"
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let merged = |conflicts| {
+            let mut doc = base
+                .parse::<Document>()
+                .expect("test doc should be valid toml");
+            let options = MergeOptions {
+                arrays: ArrayStrategy::Replace,
+                conflicts,
+            };
+            merge_documents(&mut doc, &overlay, &options).map(|_| doc.to_string())
+        };
+
+        assert_eq!(
+            merged(ConflictStrategy::Overlay).unwrap(),
+            "ports = [443]\n\n[[bin]]\nname = \"tomatillo\"\n"
+        );
+        assert_eq!(merged(ConflictStrategy::Base).unwrap(), base);
+        let err = merged(ConflictStrategy::Error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the two files have different values for ports"
+        );
+
+        let base = "[[bin]]\nname = \"tomato\"\n";
+        let overlay = "[[bin]]\nname = \"tomatillo\"\n[[bin]]\nname = \"tomato\"\n"
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let mut doc = base
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let options = MergeOptions {
+            arrays: ArrayStrategy::Replace,
+            conflicts: ConflictStrategy::Error,
+        };
+        let err = merge_documents(&mut doc, &overlay, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the two files have different values for bin"
+        );
+        assert_eq!(doc.to_string(), base);
+    }
+}
@@ -0,0 +1,241 @@
+//! Let environment variables override the values in a document, the way config crates
+//! do for services: with a prefix of `APP` and a separator of `__`, `APP__DB__URL`
+//! overrides `db.url`.
+
+use toml_edit::{Document, Item, Value};
+
+use crate::{get_key, set_key, KeySegment, Keyspec};
+
+/// Override values in the document with any environment variables named for their keys,
+/// responding with each key that was overridden and the variable that did it. Each part
+/// of a variable's name matches a key in any case, with anything that can't be in a
+/// variable name written as `_`. Values take the type of the value they replace. The
+/// prefix can't be empty, or every variable in the environment would be a candidate.
+/// Given the keys about to be read, only variables for those keys, or for the tables
+/// holding them or inside them, are applied, so a stray variable elsewhere can't get in
+/// the way.
+pub fn apply_overrides(
+    toml: &mut Document,
+    prefix: &str,
+    separator: &str,
+    vars: impl Iterator<Item = (String, String)>,
+    wanted: Option<&[Keyspec]>,
+) -> Result<Vec<(Keyspec, String)>, anyhow::Error> {
+    if prefix.is_empty() {
+        anyhow::bail!("the environment variable prefix can't be empty");
+    }
+    if separator.is_empty() {
+        anyhow::bail!("the environment variable separator can't be empty");
+    }
+    let lead = format!("{prefix}{separator}");
+    let mut vars: Vec<(String, String)> = vars
+        .filter(|(name, _)| name.starts_with(&lead) && name.len() > lead.len())
+        .collect();
+    // Apply overrides in a predictable order, so a table is overridden before its keys.
+    vars.sort();
+
+    let mut applied = Vec::new();
+    for (name, text) in vars {
+        let parts: Vec<&str> = name[lead.len()..].split(separator).collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            continue;
+        }
+        let key = resolve(toml.as_item(), &parts);
+        if wanted.is_some_and(|wanted| !wanted.iter().any(|w| overlaps(w, &key))) {
+            continue;
+        }
+        let existing = get_key(toml, &key).map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+        let value = coerce(&text, &existing).map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+        set_key(toml, &key, &value).map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+        applied.push((key, name));
+    }
+    Ok(applied)
+}
+
+/// True if one key is the other, or is inside it.
+fn overlaps(left: &Keyspec, right: &Keyspec) -> bool {
+    left.subkeys
+        .iter()
+        .zip(right.subkeys.iter())
+        .all(|(l, r)| l == r)
+}
+
+/// Turn the parts of a variable name into a key, matching them to the keys already in
+/// the document where possible. Parts that match nothing become lowercase keys.
+fn resolve(root: &Item, parts: &[&str]) -> Keyspec {
+    let mut node = Some(root);
+    let mut subkeys = Vec::new();
+    for part in parts {
+        let segment = match node {
+            Some(n) if n.is_array() || n.is_array_of_tables() => match part.parse::<usize>() {
+                Ok(idx) => KeySegment::Index(idx),
+                Err(_) => KeySegment::Name(part.to_lowercase()),
+            },
+            Some(n) => n
+                .as_table_like()
+                .and_then(|table| {
+                    table
+                        .iter()
+                        .find(|(k, _)| env_name(k) == part.to_uppercase())
+                        .map(|(k, _)| KeySegment::Name(k.to_string()))
+                })
+                .unwrap_or_else(|| KeySegment::Name(part.to_lowercase())),
+            None => KeySegment::Name(part.to_lowercase()),
+        };
+        node = node.and_then(|n| match &segment {
+            KeySegment::Name(k) => n.get(k.as_str()),
+            KeySegment::Index(idx) => n.get(*idx),
+        });
+        subkeys.push(segment);
+    }
+    Keyspec { subkeys }
+}
+
+/// A key as it would be written in an environment variable name.
+fn env_name(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Read the text of an environment variable as a value of the same type as the one it
+/// replaces. Anything that replaces nothing is a string.
+fn coerce(text: &str, existing: &Item) -> Result<Value, anyhow::Error> {
+    let value = match existing {
+        Item::None => Value::from(text),
+        Item::Value(Value::String(_)) => Value::from(text),
+        Item::Value(Value::Integer(_)) => Value::from(
+            text.trim()
+                .parse::<i64>()
+                .map_err(|_| anyhow::anyhow!("{text} is not an integer"))?,
+        ),
+        Item::Value(Value::Float(_)) => Value::from(
+            text.trim()
+                .parse::<f64>()
+                .map_err(|_| anyhow::anyhow!("{text} is not a number"))?,
+        ),
+        Item::Value(Value::Boolean(_)) => match text.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Value::from(true),
+            "false" | "0" | "no" | "off" => Value::from(false),
+            _ => anyhow::bail!("{text} is not a boolean"),
+        },
+        Item::Value(Value::Datetime(_)) => Value::from(
+            text.trim()
+                .parse::<toml_edit::Datetime>()
+                .map_err(|_| anyhow::anyhow!("{text} is not a datetime"))?,
+        ),
+        // Arrays and tables are written as toml: `["a", "b"]` or `{ a = 1 }`.
+        Item::Value(Value::Array(_)) | Item::ArrayOfTables(_) => match text.parse::<Value>() {
+            Ok(v @ Value::Array(_)) => v,
+            _ => anyhow::bail!("{text} is not a toml array"),
+        },
+        Item::Value(Value::InlineTable(_)) | Item::Table(_) => match text.parse::<Value>() {
+            Ok(v @ Value::InlineTable(_)) => v,
+            _ => anyhow::bail!("{text} is not a toml inline table"),
+        },
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn env_overrides() {
+        let toml = r#"
+[db]
+url = "sqlite://"
+pool = 5
+verbose = false
+hosts = ["a"]
+
+[package]
+rust-version = "1.70"
+"#;
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let vars = [
+            ("APP__DB__URL", "postgres://localhost"),
+            ("APP__DB__POOL", "10"),
+            ("APP__DB__VERBOSE", "yes"),
+            ("APP__DB__HOSTS", r#"["b", "c"]"#),
+            ("APP__DB__USER", "tomato"),
+            ("APP__PACKAGE__RUST_VERSION", "1.72"),
+            ("OTHER__DB__URL", "nope"),
+            ("APP_DB_URL", "nope"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        let applied = apply_overrides(&mut doc, "APP", "__", vars.clone().into_iter(), None)
+            .expect("overrides should apply");
+        let keys: Vec<String> = applied.iter().map(|(key, _)| key.to_string()).collect();
+        assert_eq!(
+            keys,
+            [
+                "db.hosts",
+                "db.pool",
+                "db.url",
+                "db.user",
+                "db.verbose",
+                "package.rust-version"
+            ]
+        );
+        let get = |doc: &mut Document, key: &str| {
+            get_key(doc, &Keyspec::from_str(key).unwrap())
+                .unwrap()
+                .to_string()
+                .trim()
+                .to_string()
+        };
+        assert_eq!(get(&mut doc, "db.url"), r#""postgres://localhost""#);
+        assert_eq!(get(&mut doc, "db.pool"), "10");
+        assert_eq!(get(&mut doc, "db.verbose"), "true");
+        assert_eq!(get(&mut doc, "db.hosts"), r#"["b", "c"]"#);
+        assert_eq!(get(&mut doc, "db.user"), r#""tomato""#);
+        assert_eq!(get(&mut doc, "package.rust-version"), r#""1.72""#);
+
+        let bad = [("APP__DB__POOL".to_string(), "many".to_string())];
+        let err = apply_overrides(&mut doc, "APP", "__", bad.into_iter(), None).unwrap_err();
+        assert_eq!(err.to_string(), "APP__DB__POOL: many is not an integer");
+
+        let err = apply_overrides(&mut doc, "", "__", vars.into_iter(), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the environment variable prefix can't be empty"
+        );
+        assert_eq!(get(&mut doc, "db.pool"), "10");
+    }
+
+    #[test]
+    fn stray_overrides() {
+        let mut doc = "[db]\nurl = \"sqlite://\"\npool = 5\n"
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let vars = || {
+            [("APP__DB__URL__X", "nope"), ("APP__DB__POOL", "10")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into_iter()
+        };
+
+        let err = apply_overrides(&mut doc, "APP", "__", vars(), None).unwrap_err();
+        assert!(err.to_string().starts_with("APP__DB__URL__X: "));
+
+        let wanted = [Keyspec::from_str("db.pool").unwrap()];
+        let applied = apply_overrides(&mut doc, "APP", "__", vars(), Some(&wanted))
+            .expect("the stray variable should be skipped");
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].1, "APP__DB__POOL");
+
+        let wanted = [Keyspec::from_str("db").unwrap()];
+        assert!(apply_overrides(&mut doc, "APP", "__", vars(), Some(&wanted)).is_err());
+    }
+}
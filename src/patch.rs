@@ -0,0 +1,437 @@
+/// Apply patches in the spirit of json patch (RFC 6902) to toml documents, with
+/// operations addressed by tomato keys instead of json pointers. `tomato diff --patch`
+/// writes patches in this form.
+use std::fmt::Display;
+use std::str::FromStr;
+
+use toml_edit::{Document, Item, Value};
+
+use crate::json::json_to_value;
+use crate::merge::same;
+use crate::{get_in_node, get_key, remove_key, set_key, Change, KeySegment, Keyspec};
+
+#[derive(Clone, Debug)]
+/// One operation in a patch.
+pub enum PatchOp {
+    Add { path: Keyspec, value: Value },
+    Remove { path: Keyspec },
+    Replace { path: Keyspec, value: Value },
+    Move { from: Keyspec, path: Keyspec },
+    Copy { from: Keyspec, path: Keyspec },
+    Test { path: Keyspec, value: Value },
+}
+
+impl PatchOp {
+    /// The name of this operation, as it is spelled in a patch.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PatchOp::Add { .. } => "add",
+            PatchOp::Remove { .. } => "remove",
+            PatchOp::Replace { .. } => "replace",
+            PatchOp::Move { .. } => "move",
+            PatchOp::Copy { .. } => "copy",
+            PatchOp::Test { .. } => "test",
+        }
+    }
+
+    /// The key this operation writes to, or checks.
+    pub fn path(&self) -> &Keyspec {
+        match self {
+            PatchOp::Add { path, .. }
+            | PatchOp::Remove { path }
+            | PatchOp::Replace { path, .. }
+            | PatchOp::Move { path, .. }
+            | PatchOp::Copy { path, .. }
+            | PatchOp::Test { path, .. } => path,
+        }
+    }
+
+    /// Apply this operation to the document, responding with a record of each key it changed.
+    pub fn apply(&self, toml: &mut Document) -> Result<Vec<Change>, anyhow::Error> {
+        let changes = match self {
+            PatchOp::Add { path, value } => {
+                let original = add(toml, path, value.clone())?;
+                vec![Change::new("add", path, original, toml)?]
+            }
+            PatchOp::Remove { path } => {
+                let original = remove(toml, path)?;
+                vec![Change::new("remove", path, original, toml)?]
+            }
+            PatchOp::Replace { path, value } => {
+                let original = replace(toml, path, value.clone())?;
+                vec![Change::new("replace", path, original, toml)?]
+            }
+            PatchOp::Move { from, path } => {
+                let value = remove(toml, from)?;
+                let moved = Change::new("remove", from, value.clone(), toml)?;
+                let original = add(toml, path, to_value(value, from)?)?;
+                vec![moved, Change::new("add", path, original, toml)?]
+            }
+            PatchOp::Copy { from, path } => {
+                let value = to_value(existing(toml, from)?, from)?;
+                let original = add(toml, path, value)?;
+                vec![Change::new("add", path, original, toml)?]
+            }
+            PatchOp::Test { path, value } => {
+                let found = get_key(toml, path)?;
+                let expected = Item::Value(value.clone());
+                if !same(&found, &expected) {
+                    let found = if found.is_none() {
+                        "missing".to_string()
+                    } else {
+                        inline(&found)
+                    };
+                    anyhow::bail!("{path} is {found}, not {}", inline(&expected));
+                }
+                Vec::new()
+            }
+        };
+        Ok(changes)
+    }
+}
+
+impl Display for PatchOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchOp::Move { from, path } | PatchOp::Copy { from, path } => {
+                write!(f, "{} {from} to {path}", self.name())
+            }
+            _ => write!(f, "{} {}", self.name(), self.path()),
+        }
+    }
+}
+
+/// Parse a patch: a json array of objects like `{"op": "replace", "path": "db.pool",
+/// "value": 10}`. `move` and `copy` take a `from` key instead of a value. Values are
+/// converted using their json types, and typed datetimes become datetimes.
+pub fn parse_patch(input: &str) -> Result<Vec<PatchOp>, anyhow::Error> {
+    let parsed: Vec<serde_json::Value> = serde_json::from_str(input)
+        .map_err(|e| anyhow::anyhow!("The patch is not valid json: {e}"))?;
+    parsed
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| {
+            let field = |name: &str| {
+                entry.get(name).ok_or_else(|| {
+                    anyhow::anyhow!("operation {} is missing the field '{name}'", idx + 1)
+                })
+            };
+            let key = |name: &str| {
+                let text = field(name)?.as_str().ok_or_else(|| {
+                    anyhow::anyhow!("operation {}: '{name}' must be a string", idx + 1)
+                })?;
+                Keyspec::from_str(text)
+            };
+            let op = field("op")?
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("operation {}: 'op' must be a string", idx + 1))?;
+            let path = key("path")?;
+            match op {
+                "add" => Ok(PatchOp::Add {
+                    path,
+                    value: json_to_value(field("value")?)?,
+                }),
+                "remove" => Ok(PatchOp::Remove { path }),
+                "replace" => Ok(PatchOp::Replace {
+                    path,
+                    value: json_to_value(field("value")?)?,
+                }),
+                "move" => Ok(PatchOp::Move {
+                    from: key("from")?,
+                    path,
+                }),
+                "copy" => Ok(PatchOp::Copy {
+                    from: key("from")?,
+                    path,
+                }),
+                "test" => Ok(PatchOp::Test {
+                    path,
+                    value: json_to_value(field("value")?)?,
+                }),
+                _ => anyhow::bail!("operation {}: '{op}' is not a known operation", idx + 1),
+            }
+        })
+        .collect()
+}
+
+/// Apply every operation in the patch in order, responding with a record of the changes.
+/// Stops at the first operation that fails, including a `test` whose value doesn't match;
+/// the caller is expected to discard the document in that case so a patch is all-or-nothing.
+pub fn apply_patch(toml: &mut Document, ops: &[PatchOp]) -> Result<Vec<Change>, anyhow::Error> {
+    let mut changes = Vec::new();
+    for (idx, op) in ops.iter().enumerate() {
+        let made = op
+            .apply(toml)
+            .map_err(|e| anyhow::anyhow!("operation {} ({op}) failed: {e}", idx + 1))?;
+        changes.extend(made);
+    }
+    Ok(changes)
+}
+
+/// Split a key into the key of its parent and its last segment.
+fn split_key(key: &Keyspec) -> Result<(Keyspec, KeySegment), anyhow::Error> {
+    let mut parent = key.clone();
+    match parent.subkeys.pop() {
+        Some(last) => Ok((parent, last)),
+        None => anyhow::bail!("a patch operation needs a key"),
+    }
+}
+
+/// Find the node at the given key to change it, if it's there.
+fn node_mut<'a>(toml: &'a mut Document, key: &'a Keyspec) -> Option<&'a mut Item> {
+    let mut node = toml.as_item_mut();
+    for k in key.subkeys.iter() {
+        node = get_in_node(k, node)?;
+        if node.is_none() {
+            return None;
+        }
+    }
+    Some(node)
+}
+
+/// The value at the key, which must be there.
+fn existing(toml: &mut Document, key: &Keyspec) -> Result<Item, anyhow::Error> {
+    match get_key(toml, key)? {
+        Item::None => anyhow::bail!("key {key} not found in toml file"),
+        found => Ok(found),
+    }
+}
+
+/// Write a value at the key. Inside an array the key is a position, and the value is
+/// inserted there; `-` or the length of the array adds it to the end. Anywhere else the
+/// value is set, replacing whatever was there and keeping its comments.
+fn add(toml: &mut Document, key: &Keyspec, value: Value) -> Result<Item, anyhow::Error> {
+    let (parent_key, last) = split_key(key)?;
+    let parent =
+        node_mut(toml, &parent_key).ok_or_else(|| anyhow::anyhow!("key {parent_key} not found"))?;
+    match parent {
+        Item::Value(Value::Array(array)) => {
+            let idx = position(&last, array.len(), key)?;
+            array.insert(idx, value);
+            Ok(Item::None)
+        }
+        Item::ArrayOfTables(aot) => {
+            let idx = position(&last, aot.len(), key)?;
+            let table = match value {
+                Value::InlineTable(table) => table.into_table(),
+                _ => anyhow::bail!("only tables can go in the array of tables at {parent_key}"),
+            };
+            let mut tables: Vec<toml_edit::Table> = aot.iter().cloned().collect();
+            tables.insert(idx, table);
+            aot.clear();
+            tables.into_iter().for_each(|t| aot.push(t));
+            Ok(Item::None)
+        }
+        _ => set_key(toml, key, &value),
+    }
+}
+
+/// Write a value in place of the one at the key, which must be there. Inside an array
+/// the element at that position is swapped out, keeping its comments, rather than a new
+/// one being inserted.
+fn replace(toml: &mut Document, key: &Keyspec, value: Value) -> Result<Item, anyhow::Error> {
+    let original = existing(toml, key)?;
+    let (parent_key, last) = split_key(key)?;
+    match (node_mut(toml, &parent_key), last) {
+        (Some(Item::ArrayOfTables(aot)), KeySegment::Index(idx)) => {
+            let table = match value {
+                Value::InlineTable(table) => table.into_table(),
+                _ => anyhow::bail!("only tables can go in the array of tables at {parent_key}"),
+            };
+            if let Some(slot) = aot.get_mut(idx) {
+                *slot = table;
+            }
+        }
+        _ => {
+            set_key(toml, key, &value)?;
+        }
+    }
+    Ok(original)
+}
+
+/// Remove the value at the key, which must be there. Later elements of an array move
+/// down to fill the gap.
+fn remove(toml: &mut Document, key: &Keyspec) -> Result<Item, anyhow::Error> {
+    let original = existing(toml, key)?;
+    let (parent_key, last) = split_key(key)?;
+    match (node_mut(toml, &parent_key), last) {
+        (Some(Item::Value(Value::Array(array))), KeySegment::Index(idx)) => {
+            let removed = array.remove(idx);
+            // The new first element takes the old one's place right after the bracket.
+            if let (0, Some(first)) = (idx, array.get_mut(0)) {
+                *first.decor_mut() = removed.decor().clone();
+            }
+        }
+        (Some(Item::ArrayOfTables(aot)), KeySegment::Index(idx)) => aot.remove(idx),
+        _ => {
+            remove_key(toml, key)?;
+        }
+    }
+    Ok(original)
+}
+
+/// The position in an array a key segment points to, which may be just past its end.
+fn position(segment: &KeySegment, len: usize, key: &Keyspec) -> Result<usize, anyhow::Error> {
+    match segment {
+        KeySegment::Index(idx) if *idx <= len => Ok(*idx),
+        KeySegment::Name(n) if n == "-" => Ok(len),
+        _ => anyhow::bail!("{key} is not a position in an array of length {len}"),
+    }
+}
+
+/// A value taken from a document, as it would be written inline.
+fn to_value(item: Item, key: &Keyspec) -> Result<Value, anyhow::Error> {
+    item.into_value()
+        .map_err(|_| anyhow::anyhow!("the value at {key} can't be moved or copied"))
+}
+
+/// A value written inline as toml, for error messages.
+fn inline(item: &Item) -> String {
+    match item.clone().into_value() {
+        Ok(mut value) => {
+            value.decor_mut().clear();
+            value.to_string()
+        }
+        Err(_) => "a table".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{diff_documents, format_patch};
+    use crate::json::JsonOptions;
+
+    const TOML: &str = r#"
+[db]
+url = "sqlite://" # for now
+pool = 5
+
+[[servers]]
+name = "alpha"
+ports = [80, 443]
+
+[[servers]]
+name = "beta"
+"#;
+
+    fn doc() -> Document {
+        TOML.parse::<Document>()
+            .expect("test string should be valid toml")
+    }
+
+    #[test]
+    fn patches_apply() {
+        let ops = parse_patch(
+            r#"[
+                {"op": "test", "path": "db.pool", "value": 5},
+                {"op": "replace", "path": "db.url", "value": "postgres://"},
+                {"op": "add", "path": "servers.0.ports.1", "value": 8080},
+                {"op": "remove", "path": "servers.0.ports.0"},
+                {"op": "copy", "from": "db.pool", "path": "servers.1.pool"},
+                {"op": "move", "from": "servers.0", "path": "servers.-"},
+                {"op": "add", "path": "db.user", "value": "tomato"}
+            ]"#,
+        )
+        .expect("this patch should parse");
+        let mut toml = doc();
+        let changes = apply_patch(&mut toml, &ops).expect("this patch should apply");
+        assert_eq!(changes.len(), 7);
+        assert_eq!(
+            toml.to_string(),
+            r#"
+[db]
+url = "postgres://" # for now
+pool = 5
+user = "tomato"
+
+[[servers]]
+name = "beta"
+pool = 5
+
+[[servers]]
+name = "alpha"
+ports = [8080, 443]
+"#
+        );
+    }
+
+    #[test]
+    fn failed_tests_stop_the_patch() {
+        let ops = parse_patch(
+            r#"[
+                {"op": "replace", "path": "db.pool", "value": 10},
+                {"op": "test", "path": "db.user", "value": "tomato"}
+            ]"#,
+        )
+        .unwrap();
+        let err = apply_patch(&mut doc(), &ops).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"operation 2 (test db.user) failed: db.user is missing, not "tomato""#
+        );
+
+        let ops = parse_patch(r#"[{"op": "remove", "path": "db.user"}]"#).unwrap();
+        assert!(apply_patch(&mut doc(), &ops).is_err());
+        assert!(parse_patch(r#"[{"op": "move", "path": "a"}]"#).is_err());
+        assert!(parse_patch(r#"[{"op": "frob", "path": "a"}]"#).is_err());
+    }
+
+    #[test]
+    fn replace_swaps_elements() {
+        let ops = parse_patch(
+            r#"[
+                {"op": "replace", "path": "servers.0.ports.1", "value": 8443},
+                {"op": "replace", "path": "servers.1", "value": {"name": "gamma"}}
+            ]"#,
+        )
+        .unwrap();
+        let mut toml = doc();
+        let changes = apply_patch(&mut toml, &ops).expect("this patch should apply");
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            toml.to_string(),
+            r#"
+[db]
+url = "sqlite://" # for now
+pool = 5
+
+[[servers]]
+name = "alpha"
+ports = [80, 8443]
+
+[[servers]]
+name = "gamma"
+"#
+        );
+
+        let ops = parse_patch(r#"[{"op": "replace", "path": "servers.2", "value": {}}]"#).unwrap();
+        assert!(apply_patch(&mut doc(), &ops).is_err());
+        let ops = parse_patch(r#"[{"op": "replace", "path": "servers.0", "value": 1}]"#).unwrap();
+        assert!(apply_patch(&mut doc(), &ops).is_err());
+    }
+
+    #[test]
+    fn diffs_round_trip() {
+        let left = doc();
+        let right = r#"
+[db]
+pool = 8
+user = "tomato"
+
+[[servers]]
+name = "gamma"
+ports = [80]
+
+[[servers]]
+name = "beta"
+"#
+        .parse::<Document>()
+        .unwrap();
+        let diffs = diff_documents(&left, &right, false);
+        let ops = parse_patch(&format_patch(&diffs, &JsonOptions::default())).unwrap();
+        let mut patched = doc();
+        apply_patch(&mut patched, &ops).expect("a diff should apply as a patch");
+        assert!(diff_documents(&patched, &right, false).is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+/// Dialects for shells other than bash. The tree walk they share lives in bash.rs.
+use crate::bash::{quote, Dialect, Kind};
+
+/// Fish: lists, but nothing like an associative array, so tables are flattened.
+pub struct Fish {
+    /// Set global exported variables (`set -gx`) instead of local ones (`set -l`).
+    pub exported: bool,
+}
+
+impl Dialect for Fish {
+    /// Inside single quotes fish only treats `\'` and `\\` specially.
+    fn quote(&self, s: &str) -> String {
+        format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        items.join(" ")
+    }
+
+    fn map(&self, _entries: &[(String, String)]) -> Option<String> {
+        None
+    }
+
+    fn assign(&self, name: &str, _kind: Kind, value: &str) -> String {
+        let scope = if self.exported { "-gx" } else { "-l" };
+        format!("set {scope} {name} {value}").trim_end().to_string()
+    }
+}
+
+/// Zsh: like bash, with its own spelling of associative arrays.
+pub struct Zsh;
+
+impl Dialect for Zsh {
+    fn quote(&self, s: &str) -> String {
+        quote(s)
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        format!("( {} )", items.join(" "))
+    }
+
+    fn map(&self, entries: &[(String, String)]) -> Option<String> {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("{} {v}", quote(k)))
+            .collect();
+        Some(format!("( {} )", entries.join(" ")))
+    }
+
+    fn assign(&self, name: &str, kind: Kind, value: &str) -> String {
+        match kind {
+            Kind::Scalar => format!("{name}={value}"),
+            Kind::List => format!("typeset -a {name}={value}"),
+            Kind::Map => format!("typeset -A {name}={value}"),
+        }
+    }
+}
+
+/// PowerShell: arrays and ordered hashtables, which nest.
+pub struct PowerShell;
+
+impl Dialect for PowerShell {
+    /// PowerShell reads the typographic single quotes as quotes too, so every kind
+    /// of single quote is doubled.
+    fn quote(&self, s: &str) -> String {
+        let mut quoted = String::from("'");
+        for c in s.chars() {
+            if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+                quoted.push(c);
+            }
+            quoted.push(c);
+        }
+        quoted.push('\'');
+        quoted
+    }
+
+    fn boolean(&self, b: bool) -> String {
+        if b { "$true" } else { "$false" }.to_string()
+    }
+
+    fn float(&self, f: f64) -> String {
+        if f.is_nan() {
+            "[double]::NaN".to_string()
+        } else if f.is_infinite() {
+            if f > 0.0 {
+                "[double]::PositiveInfinity"
+            } else {
+                "[double]::NegativeInfinity"
+            }
+            .to_string()
+        } else {
+            format!("{f:?}")
+        }
+    }
+
+    fn nothing(&self) -> String {
+        "$null".to_string()
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        format!("@({})", items.join(", "))
+    }
+
+    fn map(&self, entries: &[(String, String)]) -> Option<String> {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("{} = {v}", self.quote(k)))
+            .collect();
+        Some(format!("[ordered]@{{{}}}", entries.join("; ")))
+    }
+
+    fn nests(&self) -> bool {
+        true
+    }
+
+    fn assign(&self, name: &str, _kind: Kind, value: &str) -> String {
+        format!("${name} = {value}")
+    }
+}
+
+/// Nushell: lists and records, which nest, and real datetimes.
+pub struct Nushell;
+
+impl Nushell {
+    /// Record keys can go bare if they look like identifiers.
+    fn key(&self, key: &str) -> String {
+        let bare = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+        if bare {
+            key.to_string()
+        } else {
+            self.quote(key)
+        }
+    }
+}
+
+impl Dialect for Nushell {
+    /// Single-quoted strings have no escapes at all, so they're used unless the
+    /// string holds a single quote. Double-quoted strings have json-like escapes,
+    /// and don't interpolate.
+    fn quote(&self, s: &str) -> String {
+        if !s.contains('\'') {
+            return format!("'{s}'");
+        }
+        let mut quoted = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '\\' => quoted.push_str(r"\\"),
+                '"' => quoted.push_str("\\\""),
+                '\n' => quoted.push_str(r"\n"),
+                '\t' => quoted.push_str(r"\t"),
+                '\r' => quoted.push_str(r"\r"),
+                c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    fn boolean(&self, b: bool) -> String {
+        b.to_string()
+    }
+
+    fn float(&self, f: f64) -> String {
+        if f.is_finite() {
+            format!("{f:?}")
+        } else {
+            // Nushell has no literals for these.
+            self.quote(&f.to_string())
+        }
+    }
+
+    /// Anything with a date is a nushell datetime literal. A bare time isn't.
+    fn datetime(&self, dt: &toml_edit::Datetime) -> String {
+        if dt.date.is_some() {
+            dt.to_string()
+        } else {
+            self.quote(&dt.to_string())
+        }
+    }
+
+    fn nothing(&self) -> String {
+        "null".to_string()
+    }
+
+    fn list(&self, items: &[String]) -> String {
+        format!("[{}]", items.join(", "))
+    }
+
+    fn map(&self, entries: &[(String, String)]) -> Option<String> {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("{}: {v}", self.key(k)))
+            .collect();
+        Some(format!("{{{}}}", entries.join(", ")))
+    }
+
+    fn nests(&self) -> bool {
+        true
+    }
+
+    fn assign(&self, name: &str, _kind: Kind, value: &str) -> String {
+        format!("let {name} = {value}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bash::{format_shell, BashOptions};
+    use crate::{get_key, Keyspec};
+    use std::str::FromStr;
+    use toml_edit::Document;
+
+    const TOML: &str = r#"
+name = "it's $(id)"
+ports = [80, 443]
+when = 2023-09-24T10:00:00Z
+ok = true
+ratio = 0.5
+
+[server]
+host = "localhost"
+tls = { cert = "a.pem" }
+
+[[server.backend]]
+name = "one"
+"#;
+
+    fn shell_output(shell: &dyn Dialect, key: &str) -> String {
+        let mut doc = TOML
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let key = Keyspec::from_str(key).unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+        format_shell(shell, &item, "v", &BashOptions::default())
+    }
+
+    #[test]
+    fn fish_output() {
+        let fish = Fish { exported: false };
+        assert_eq!(shell_output(&fish, "name"), r"set -l v 'it\'s $(id)'");
+        assert_eq!(shell_output(&fish, "ports"), "set -l v 80 443");
+        assert_eq!(shell_output(&fish, "ok"), "set -l v 1");
+        assert_eq!(shell_output(&fish, "missing"), "set -l v");
+        assert_eq!(
+            shell_output(&Fish { exported: true }, "server"),
+            "set -gx V_HOST 'localhost'\nset -gx V_TLS_CERT 'a.pem'\nset -gx V_BACKEND_0_NAME 'one'"
+        );
+    }
+
+    #[test]
+    fn zsh_output() {
+        assert_eq!(shell_output(&Zsh, "name"), r"v='it'\''s $(id)'");
+        assert_eq!(shell_output(&Zsh, "ports"), "typeset -a v=( 80 443 )");
+        assert_eq!(
+            shell_output(&Zsh, "server.tls"),
+            "typeset -A v=( 'cert' 'a.pem' )"
+        );
+    }
+
+    #[test]
+    fn powershell_output() {
+        assert_eq!(shell_output(&PowerShell, "name"), "$v = 'it''s $(id)'");
+        assert_eq!(shell_output(&PowerShell, "ports"), "$v = @(80, 443)");
+        assert_eq!(shell_output(&PowerShell, "ok"), "$v = $true");
+        assert_eq!(shell_output(&PowerShell, "missing"), "$v = $null");
+        assert_eq!(
+            shell_output(&PowerShell, "server"),
+            "$v = [ordered]@{'host' = 'localhost'; 'tls' = [ordered]@{'cert' = 'a.pem'}; 'backend' = @([ordered]@{'name' = 'one'})}"
+        );
+    }
+
+    #[test]
+    fn nushell_output() {
+        assert_eq!(shell_output(&Nushell, "name"), r#"let v = "it's $(id)""#);
+        assert_eq!(shell_output(&Nushell, "ports"), "let v = [80, 443]");
+        assert_eq!(
+            shell_output(&Nushell, "when"),
+            "let v = 2023-09-24T10:00:00Z"
+        );
+        assert_eq!(shell_output(&Nushell, "ratio"), "let v = 0.5");
+        assert_eq!(
+            shell_output(&Nushell, "server"),
+            "let v = {host: 'localhost', tls: {cert: 'a.pem'}, backend: [{name: 'one'}]}"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+/// Implement csv and tsv serialization for arrays of tables, with a header row
+/// of every key found in any of the tables.
+use toml_edit::Item;
+
+use crate::json::{to_json, JsonOptions};
+use crate::Keyspec;
+
+/// Format an item as rows of delimited text. An array of tables gets a header row made
+/// from the union of its keys, in the order they first appear, and one row per table.
+/// A single table is one row. An array of anything else is one value per row, with
+/// no header. Nested values are written as json.
+pub fn format_delimited(item: &Item, delimiter: u8) -> String {
+    let rows = match to_json(item, &JsonOptions::default()) {
+        serde_json::Value::Array(items) if is_tabular(&items) => tabulate(&items),
+        obj @ serde_json::Value::Object(_) => tabulate(&[obj]),
+        serde_json::Value::Array(items) => items.iter().map(|v| vec![cell(v)]).collect(),
+        serde_json::Value::Null => Vec::new(),
+        v => vec![vec![cell(&v)]],
+    };
+    write_rows(&rows, delimiter)
+}
+
+/// Format the results of several lookups as a header row of the keys and a single
+/// row of their values.
+pub fn format_delimited_lookups(found: &[(Keyspec, Item)], delimiter: u8) -> String {
+    let options = JsonOptions::default();
+    let header = found.iter().map(|(key, _)| key.to_string()).collect();
+    let values = found
+        .iter()
+        .map(|(_, item)| cell(&to_json(item, &options)))
+        .collect();
+    write_rows(&[header, values], delimiter)
+}
+
+/// True if the array is made of tables, and so has columns.
+fn is_tabular(items: &[serde_json::Value]) -> bool {
+    !items.is_empty() && items.iter().all(|v| v.is_object())
+}
+
+/// Turn a list of json objects into a header row and one row per object. Objects
+/// without one of the keys get an empty cell for it.
+fn tabulate(items: &[serde_json::Value]) -> Vec<Vec<String>> {
+    let mut columns: Vec<&String> = Vec::new();
+    for obj in items.iter().filter_map(|v| v.as_object()) {
+        for key in obj.keys() {
+            if !columns.contains(&key) {
+                columns.push(key);
+            }
+        }
+    }
+    let mut rows = vec![columns.iter().map(|k| k.to_string()).collect()];
+    rows.extend(items.iter().filter_map(|v| v.as_object()).map(|obj| {
+        columns
+            .iter()
+            .map(|k| obj.get(k.as_str()).map(cell).unwrap_or_default())
+            .collect()
+    }));
+    rows
+}
+
+/// The text of a single cell: strings as they are, nothing for null, and json for
+/// everything else.
+fn cell(v: &serde_json::Value) -> String {
+    match v {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => "".to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Write the rows, quoting any cell that needs it.
+fn write_rows(rows: &[Vec<String>], delimiter: u8) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    for row in rows {
+        writer
+            .write_record(row)
+            .expect("writing to memory should not fail");
+    }
+    let bytes = writer
+        .into_inner()
+        .expect("writing to memory should not fail");
+    String::from_utf8_lossy(&bytes)
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_key;
+    use std::str::FromStr;
+    use toml_edit::Document;
+
+    #[test]
+    fn csv_output() {
+        let toml = r#"
+[[servers]]
+name = "alpha"
+ip = "10.0.0.1"
+roles = ["web", "db"]
+
+[[servers]]
+name = "beta, the second"
+port = 8080
+notes = "says \"hi\"\nand bye"
+"#;
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test string should be valid toml");
+        let key = Keyspec::from_str("servers").unwrap();
+        let item = get_key(&mut doc, &key).unwrap();
+
+        assert_eq!(
+            format_delimited(&item, b','),
+            r#"name,ip,roles,port,notes
+alpha,10.0.0.1,"[""web"",""db""]",,
+"beta, the second",,,8080,"says ""hi""
+and bye""#
+        );
+        assert_eq!(
+            format_delimited(&item, b'\t'),
+            "name\tip\troles\tport\tnotes\n\
+             alpha\t10.0.0.1\t\"[\"\"web\"\",\"\"db\"\"]\"\t\t\n\
+             beta, the second\t\t\t8080\t\"says \"\"hi\"\"\nand bye\""
+        );
+
+        let keys = crate::KeyList::from_str("servers.name,missing,port=80").unwrap();
+        let found = crate::get_keys(&mut doc, &keys, None).unwrap();
+        assert_eq!(
+            format_delimited_lookups(&found, b','),
+            "servers.name,missing,port\n,,80"
+        );
+    }
+}
@@ -0,0 +1,233 @@
+/// Implement yaml serialization for the toml_edit data structures. We write this
+/// by hand rather than going through serde so that toml datetimes come out as yaml
+/// timestamps and tables keep the order they had in the toml file.
+use toml_edit::{Item, Value};
+
+/// An intermediate form of the data, shaped the way yaml wants to write it.
+enum Node {
+    /// A scalar, already rendered as yaml text.
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+/// Turn a toml_edit::Item into a yaml node. Missing items become yaml nulls.
+fn item_to_node(item: &Item) -> Node {
+    match item {
+        Item::None => Node::Scalar("null".to_string()),
+        Item::Value(v) => value_to_node(v),
+        Item::Table(table) => Node::Map(
+            table
+                .iter()
+                .map(|(k, v)| (yaml_string(k), item_to_node(v)))
+                .collect(),
+        ),
+        Item::ArrayOfTables(aot) => Node::Seq(
+            aot.iter()
+                .map(|table| item_to_node(&Item::Table(table.clone())))
+                .collect(),
+        ),
+    }
+}
+
+/// Turn a toml_edit::Value into a yaml node.
+fn value_to_node(v: &Value) -> Node {
+    match v {
+        Value::String(s) => Node::Scalar(yaml_string(s.value())),
+        Value::Integer(i) => Node::Scalar(i.value().to_string()),
+        Value::Float(f) => {
+            let f = *f.value();
+            let text = if f.is_nan() {
+                ".nan".to_string()
+            } else if f.is_infinite() {
+                if f > 0.0 { ".inf" } else { "-.inf" }.to_string()
+            } else {
+                // Debug formatting keeps the decimal point on whole numbers, so
+                // they stay floats when read back.
+                format!("{f:?}")
+            };
+            Node::Scalar(text)
+        }
+        Value::Boolean(b) => Node::Scalar(b.value().to_string()),
+        Value::Datetime(dt) => {
+            let dt = dt.value();
+            // Anything with a date is a valid yaml timestamp. A bare time isn't,
+            // and some yaml parsers would read it as a base-60 number, so quote it.
+            if dt.date.is_some() {
+                Node::Scalar(dt.to_string())
+            } else {
+                Node::Scalar(double_quoted(&dt.to_string()))
+            }
+        }
+        Value::Array(array) => Node::Seq(array.iter().map(value_to_node).collect()),
+        Value::InlineTable(table) => Node::Map(
+            table
+                .iter()
+                .map(|(k, v)| (yaml_string(k), value_to_node(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// Words that some yaml parser or other will read as something other than a string.
+const RESERVED: &[&str] = &[
+    "true", "false", "yes", "no", "on", "off", "y", "n", "null", "~",
+];
+
+/// Render a string as a yaml scalar, leaving it unquoted only when that is
+/// unambiguous. Anything that starts with a digit or looks like a keyword is quoted.
+fn yaml_string(s: &str) -> String {
+    let plain = s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '/')
+        && !s.ends_with(' ')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '/' | ' ' | '-'))
+        && !RESERVED.contains(&s.to_lowercase().as_str());
+    if plain {
+        s.to_string()
+    } else {
+        double_quoted(s)
+    }
+}
+
+/// Json string escaping is also valid yaml double-quoted string escaping.
+fn double_quoted(s: &str) -> String {
+    serde_json::Value::String(s.to_string()).to_string()
+}
+
+/// True if the node is written on the same line as its key or dash.
+fn is_inline(node: &Node) -> bool {
+    match node {
+        Node::Scalar(_) => true,
+        Node::Seq(items) => items.is_empty(),
+        Node::Map(entries) => entries.is_empty(),
+    }
+}
+
+/// Render a node as block-style yaml lines, with no indentation of its own.
+fn render(node: &Node) -> Vec<String> {
+    match node {
+        Node::Scalar(s) => vec![s.clone()],
+        Node::Seq(items) if items.is_empty() => vec!["[]".to_string()],
+        Node::Map(entries) if entries.is_empty() => vec!["{}".to_string()],
+        Node::Seq(items) => items
+            .iter()
+            .flat_map(|item| {
+                render(item)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, line)| {
+                        if idx == 0 {
+                            format!("- {line}")
+                        } else {
+                            format!("  {line}")
+                        }
+                    })
+                    .collect::<Vec<String>>()
+            })
+            .collect(),
+        Node::Map(entries) => entries
+            .iter()
+            .flat_map(|(k, v)| {
+                let rendered = render(v);
+                if is_inline(v) {
+                    vec![format!("{k}: {}", rendered[0])]
+                } else {
+                    std::iter::once(format!("{k}:"))
+                        .chain(rendered.into_iter().map(|line| format!("  {line}")))
+                        .collect()
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Given any toml_edit::Item, serialize it as yaml.
+pub fn format_yaml(item: &Item) -> String {
+    render(&item_to_node(item)).join("\n")
+}
+
+/// Serialize a list of named items as a single yaml mapping. Keys are used as-is,
+/// without being split on dots.
+pub fn format_yaml_entries(entries: &[(String, Item)]) -> String {
+    let node = Node::Map(
+        entries
+            .iter()
+            .map(|(k, v)| (yaml_string(k), item_to_node(v)))
+            .collect(),
+    );
+    render(&node).join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_key, Keyspec};
+    use std::str::FromStr;
+    use toml_edit::Document;
+
+    #[test]
+    fn yaml_output() {
+        let toml = include_str!("../fixtures/sample.toml");
+        let mut doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+
+        let key = Keyspec::from_str("testcases.hashes.mats").unwrap();
+        let item = get_key(&mut doc, &key).expect("expected to find key testcases.hashes.mats");
+        assert_eq!(format_yaml(&item), "- potatoes\n- salt\n- oil\n- frying");
+
+        let key = Keyspec::from_str("testcases.when").unwrap();
+        let item = get_key(&mut doc, &key).expect("expected to find key testcases.when");
+        assert_eq!(format_yaml(&item), "2022-08-01T22:04:00-08:00");
+
+        let key = Keyspec::from_str("nested").unwrap();
+        let item = get_key(&mut doc, &key).expect("expected to find key nested");
+        assert_eq!(format_yaml(&item), "- entry: one\n- entry: two");
+
+        let yaml = format_yaml(doc.as_item());
+        assert_eq!(yaml, include_str!("../fixtures/sample.yaml").trim());
+    }
+
+    #[test]
+    fn yaml_scalars() {
+        let toml = r#"
+words = ["plain", "yes", "No", "0.5.0", "two words", "colon: here", "line\nbreak", ""]
+floats = [1.0, 2.5, inf, -inf, nan]
+date = 2023-09-24
+local = 2023-09-24T10:00:00
+time = 10:00:00
+empty = []
+nothing = {}
+nested = [[1, 2], [{ a = 1, b = 2 }]]
+"#;
+        let doc = toml
+            .parse::<Document>()
+            .expect("test doc should be valid toml");
+        let expected = r#"words:
+  - plain
+  - "yes"
+  - "No"
+  - "0.5.0"
+  - two words
+  - "colon: here"
+  - "line\nbreak"
+  - ""
+floats:
+  - 1.0
+  - 2.5
+  - .inf
+  - -.inf
+  - .nan
+date: 2023-09-24
+local: 2023-09-24T10:00:00
+time: "10:00:00"
+empty: []
+nothing: {}
+nested:
+  - - 1
+    - 2
+  - - a: 1
+      b: 2"#;
+        assert_eq!(format_yaml(doc.as_item()), expected);
+    }
+}